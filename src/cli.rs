@@ -1,17 +1,26 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
-#[derive(Parser, Debug)]
+use crate::{DEFAULT_TLB_ENTRIES, IsaProfile, MmioRegion, devices::{DEFAULT_KEY_STATUS_PORT, DEFAULT_KEY_DATA_PORT, DEFAULT_CONSOLE_PORT, DEFAULT_FRAMEBUFFER_ADDR, FramebufferMode, DEFAULT_RNG_PORT}};
+
+#[derive(Parser, Debug, Clone)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 /// All cli arguments will be placed into this struct, to be able to be used in main
 pub struct CliArgs {
+    #[allow(missing_docs)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Which instructions file to execute. If --simple is set, it must be labelless and have no data section. 
     /// If --simple is not set, there must be a .data/.text/.end section
+    #[arg(required_unless_present = "dap", default_value = "")]
     pub input_file: String,
 
     /// The register's starting values (defaults to all 0x0000).
     #[arg(short = 'r', long)]
     pub reg_file: Option<String>,
 
-    /// The IO's starting values (defaults to empty).
+    /// The IO's starting values (defaults to empty). It may also script how they change over time,
+    /// see the docs of `read_io_timeline`.
     #[arg(short = 'i', long)]
     pub io_file: Option<String>,
 
@@ -43,4 +52,185 @@ pub struct CliArgs {
     /// the docs.
     #[arg(short, long, default_value_t = false)]
     pub simple: bool,
+
+    /// Whether or not to translate addresses through the instruction and data TLBs. When set,
+    /// every fetch and every LD/LDB/ST/STB goes through them and may raise TLB exceptions. Entry `i`
+    /// starts out mapping virtual page `i` to physical page `i`, so with the default 16 entries
+    /// every page is mapped to itself.
+    #[arg(long, default_value_t = false)]
+    pub tlb: bool,
+
+    /// Number of entries of the instruction TLB. Only used if `--tlb` is used
+    #[arg(long, default_value_t = DEFAULT_TLB_ENTRIES)]
+    pub itlb_entries: usize,
+
+    /// Number of entries of the data TLB. Only used if `--tlb` is used
+    #[arg(long, default_value_t = DEFAULT_TLB_ENTRIES)]
+    pub dtlb_entries: usize,
+
+    /// Start in user mode instead of system mode. In user mode, privileged instructions (RDS, WRS,
+    /// EI, DI, RETI and the TLB ones) raise an exception.
+    #[arg(long, default_value_t = false)]
+    pub user: bool,
+
+    /// Address of the exception handler. Without one, any exception stops the simulation.
+    #[arg(long)]
+    pub exception_handler: Option<u16>,
+
+    /// Address `CALLS` jumps to (defaults to the exception handler).
+    #[arg(long)]
+    pub syscall_handler: Option<u16>,
+
+    /// Whether IN and OUT are privileged instructions too.
+    #[arg(long, default_value_t = false)]
+    pub protect_io: bool,
+
+    /// Subset of the ISA the program may use: sisa-i, sisa, sisa-f or sisa-sys (defaults to
+    /// sisa-sys, which allows everything). Instructions outside of it are rejected.
+    #[arg(long)]
+    pub profile: Option<IsaProfile>,
+
+    /// Project file (TOML) with default settings. The options given in the command line take
+    /// preference over the ones in the project file.
+    #[arg(long)]
+    pub project: Option<String>,
+
+    /// Don't stop when the program goes through the exact same state twice (which means it will
+    /// never halt). The instruction limit still applies.
+    #[arg(long, default_value_t = false)]
+    pub no_loop_detection: bool,
+
+    /// Attach a keyboard with the KEY-STATUS/KEY-DATA handshake. The keys are read from the given
+    /// file (one per line, in decimal, hex or as 'c'), or from the terminal if it is `-`.
+    #[arg(long)]
+    pub keyboard: Option<String>,
+
+    /// Port of the keyboard's KEY-STATUS.
+    #[arg(long, default_value_t = DEFAULT_KEY_STATUS_PORT)]
+    pub key_status_port: i16,
+
+    /// Port of the keyboard's KEY-DATA.
+    #[arg(long, default_value_t = DEFAULT_KEY_DATA_PORT)]
+    pub key_data_port: i16,
+
+    /// Ask for the value of the ports that IN reads from when nothing else provides it: on the
+    /// terminal, or reading a line from stdin if it's not a terminal. Values may be given in
+    /// decimal, hex or as 'c'.
+    #[arg(long, default_value_t = false)]
+    pub interactive_io: bool,
+
+    /// Write every value written with OUT (step, cycle, port and value) to this file once the
+    /// program is done. It is written as JSON if the file ends in `.json`, and as CSV otherwise.
+    #[arg(long)]
+    pub output_log: Option<String>,
+
+    /// Compare the values written with OUT to the ones in this file (`port,value` lines), failing
+    /// if they differ.
+    #[arg(long)]
+    pub expected_output: Option<String>,
+
+    /// Attach the DE1/DE2 board's LEDs (ports 5 and 6), pushbuttons (7), switches (8) and
+    /// 7-segment displays (9 and 10), drawing them every time the program writes to them.
+    #[arg(long, default_value_t = false)]
+    pub board: bool,
+
+    /// Script for the board's switches and pushbuttons (implies `--board`), see the docs of
+    /// `read_board_script`.
+    #[arg(long)]
+    pub board_script: Option<String>,
+
+    /// Attach a text console: every OUT to its port appends the low byte as an ASCII character to
+    /// the console, which is shown once the program is done.
+    #[arg(long, default_value_t = false)]
+    pub console: bool,
+
+    /// Port of the text console.
+    #[arg(long, default_value_t = DEFAULT_CONSOLE_PORT)]
+    pub console_port: i16,
+
+    /// Write the text console's contents to this file once the program is done (implies
+    /// `--console`).
+    #[arg(long)]
+    pub console_file: Option<String>,
+
+    /// Map a framebuffer into the memory, in `text` (80x25 ASCII characters) or `pixels` (128x64
+    /// RGB332 pixels) mode. It is drawn once the program is done.
+    #[arg(long)]
+    pub framebuffer: Option<FramebufferMode>,
+
+    /// Address where the framebuffer starts (defaults to 0xA000).
+    #[arg(long, default_value_t = DEFAULT_FRAMEBUFFER_ADDR)]
+    pub framebuffer_addr: u16,
+
+    /// Write the framebuffer's last frame to this file: as a PPM image in pixel mode, and as text
+    /// in text mode.
+    #[arg(long)]
+    pub framebuffer_out: Option<String>,
+
+    /// Also write a frame every N instructions while the program runs, to numbered files next to
+    /// the one given with `--framebuffer-out` (`screen.ppm` becomes `screen-0001.ppm`,
+    /// `screen-0002.ppm`...). Frames where nothing changed are skipped.
+    #[arg(long, requires = "framebuffer_out")]
+    pub framebuffer_every: Option<usize>,
+
+    /// Hand a region of the memory over to the IO ports, as `START-END=PORT`: LD/LDB/ST/STB
+    /// between START and END reach the port PORT + (address - START) / 2 instead of the memory.
+    /// May be given more than once, and adds to the regions of the project file.
+    #[arg(long)]
+    pub mmio: Vec<MmioRegion>,
+
+    /// Attach a pseudo-random number generator with this seed: every IN from its port gives a new
+    /// value, and the same seed always gives the same ones. Takes preference over the project
+    /// file's `rng_seed`.
+    #[arg(long)]
+    pub rng_seed: Option<u64>,
+
+    /// Port of the pseudo-random number generator.
+    #[arg(long, default_value_t = DEFAULT_RNG_PORT)]
+    pub rng_port: i16,
+
+    /// Start the interactive debugger instead of running the whole program at once (type `help`
+    /// once it starts to see the commands).
+    #[arg(long, default_value_t = false)]
+    pub debug: bool,
+
+    /// Stop before running the instruction at this address or label (implies `--debug`). May be
+    /// given more than once.
+    #[arg(long = "break")]
+    pub breakpoints: Vec<String>,
+
+    /// Debug the program from GDB (or any front-end that speaks its remote protocol) instead:
+    /// wait for it to connect to this local port (`target remote localhost:PORT`).
+    #[arg(long, value_name = "PORT")]
+    pub gdb: Option<u16>,
+
+    /// Debug the program in a full-screen view of its source, registers, memory and IO instead,
+    /// driven by single keys (shown at the bottom).
+    #[arg(long, default_value_t = false)]
+    pub tui: bool,
+
+    /// Serve an editor through the Debug Adapter Protocol, over stdin and stdout. The program (and
+    /// any other arguments) is then given by the editor when it launches it, so no input file is
+    /// needed.
+    #[arg(long, default_value_t = false)]
+    pub dap: bool,
+
+    /// Write every instruction executed to this file, one JSON object per line: the step, the PC,
+    /// the instruction, the registers and memory it wrote and the IO ports it read or wrote. Two
+    /// traces can be compared with `trace-diff`.
+    #[arg(long, value_name = "FILE")]
+    pub trace: Option<String>,
+}
+
+/// Things to do other than running a program
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Compare a trace written with `--trace` against the expected one, and report the first
+    /// instruction where they differ. Exits with 1 if they do
+    TraceDiff {
+        /// The trace that should be like the expected one
+        got: String,
+        /// The trace of the reference program (or of the simulator version that is trusted)
+        expected: String,
+    },
 }
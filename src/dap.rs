@@ -0,0 +1,338 @@
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::{debugger::{condition_failure, Debugger, Stop}, execute::{MemAddr, ProgCounter}};
+
+/// Creates the [Debugger] for the arguments of a `launch` request, with its source map (see
+/// [Debugger::set_source_map]) so that breakpoints can be set by line
+pub type Launcher = Box<dyn FnMut(&Value) -> anyhow::Result<Debugger>>;
+
+/// The only thread there is
+const THREAD: i64 = 1;
+/// The only frame there is, since SISA has no stack to speak of
+const FRAME: i64 = 1;
+const REGISTERS_SCOPE: i64 = 1;
+const MEMORY_SCOPE: i64 = 2;
+const IO_SCOPE: i64 = 3;
+/// The bytes around each label of the .data section get their own reference, starting here
+const LABEL_REFERENCES: i64 = 1000;
+/// Bytes shown around each label
+const LABEL_BYTES: i16 = 16;
+
+/// A program being debugged
+struct Session {
+    debugger: Debugger,
+    /// The source file, as given to `launch`
+    path: String,
+    /// Ids (in the debugger) of the breakpoints set by the editor
+    breakpoints: Vec<usize>,
+    stop_on_entry: bool,
+}
+
+/// Serves a [Debugger] to an editor through the Debug Adapter Protocol, over stdio (or any other
+/// pair of streams).
+///
+/// `launch` takes the `program` (a `.sisa` file) and optionally its `io` file (`.sio`), `registers`
+/// file (`.sregs`), `memory` file (with `simple`), `stopOnEntry` and any other command line `args`.
+/// Breakpoints are set by source line, on the first instruction at or after it. There is a single
+/// thread with a single frame, whose variables are the registers, the memory around each label of
+/// the .data section and the IO ports. Stepping back is supported too.
+pub struct DapServer {
+    launch: Launcher,
+    session: Option<Session>,
+    seq: i64,
+}
+
+impl DapServer {
+    #[allow(missing_docs)]
+    pub fn new(launch: Launcher) -> Self {
+        Self { launch, session: None, seq: 0 }
+    }
+
+    /// Answer the requests read from `input` until the editor disconnects
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> anyhow::Result<()> {
+        while let Some(request) = read_message(&mut input)? {
+            let done = matches!(request["command"].as_str(), Some("disconnect" | "terminate"));
+            for mut message in self.handle(&request) {
+                self.seq += 1;
+                message["seq"] = json!(self.seq);
+                let body = message.to_string();
+                write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+            }
+            output.flush()?;
+            if done { break; }
+        }
+        Ok(())
+    }
+
+    /// Answer a single request, with its response and any events that follow it
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let mut events = Vec::new();
+        let body = match command {
+            "initialize" => {
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsStepBack": true,
+                    "supportsConditionalBreakpoints": true,
+                }))
+            },
+            "launch" => {
+                // Only now can breakpoints be set, so that's when the editor is told to send them
+                let started = self.start(args);
+                if started.is_ok() { events.push(event("initialized", json!({}))); }
+                started
+            },
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                if let Some(session) = &mut self.session {
+                    if session.stop_on_entry {
+                        events.push(stopped("entry", None));
+                    } else {
+                        let stop = session.debugger.cont();
+                        events = self.stop_events(stop, 0);
+                    }
+                }
+                Ok(json!({}))
+            },
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "SISA" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_SCOPE, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_SCOPE, "expensive": false },
+                { "name": "IO ports", "variablesReference": IO_SCOPE, "expensive": false },
+            ] })),
+            "variables" => self.variables(args["variablesReference"].as_i64().unwrap_or_default()),
+            "continue" | "next" | "stepIn" | "stepOut" | "stepBack" | "reverseContinue" => match &mut self.session {
+                Some(session) => {
+                    let outputs = session.debugger.cpu().output_log().len();
+                    let stop = match command {
+                        "continue" => session.debugger.cont(),
+                        "next" => session.debugger.step_over(),
+                        "stepOut" => session.debugger.step_out(),
+                        "stepIn" => session.debugger.step(1),
+                        "stepBack" => session.debugger.step_back(1),
+                        _ => session.debugger.reverse_cont(),
+                    };
+                    events = self.stop_events(stop, outputs);
+                    Ok(json!({ "allThreadsContinued": true }))
+                },
+                None => Err("no program has been launched".to_string()),
+            },
+            "pause" => Ok(json!({})),
+            "disconnect" | "terminate" => Ok(json!({})),
+            other => Err(format!("unsupported request '{other}'")),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        let mut messages = vec![response];
+        messages.extend(events);
+        messages
+    }
+
+    fn start(&mut self, args: &Value) -> Result<Value, String> {
+        let debugger = (self.launch)(args).map_err(|e| format!("{e:#}"))?;
+        self.session = Some(Session {
+            debugger,
+            path: args["program"].as_str().unwrap_or_default().to_string(),
+            breakpoints: Vec::new(),
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+        });
+        Ok(json!({}))
+    }
+
+    /// Replace the breakpoints with the ones given, each on the first instruction at or after its
+    /// line
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let session = self.session.as_mut().ok_or("no program has been launched")?;
+        for id in session.breakpoints.drain(..) {
+            let _ = session.debugger.remove_breakpoint(id);
+        }
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<_> = requested.iter().map(|b| {
+            let line = b["line"].as_u64().unwrap_or_default() as usize;
+            match session.debugger.source_map().at_line(line).map(|(pc, l)| (pc, l.line)) {
+                Some((pc, line)) => match session.debugger.add_breakpoint(&format!("0x{pc:X}")) {
+                    Ok(id) => {
+                        if let Err(e) = session.debugger.set_condition(id, b["condition"].as_str()) {
+                            let _ = session.debugger.remove_breakpoint(id);
+                            return json!({ "verified": false, "line": line, "message": e.to_string() });
+                        }
+                        session.breakpoints.push(id);
+                        json!({ "id": id, "verified": true, "line": line })
+                    },
+                    Err(e) => json!({ "verified": false, "message": e.to_string() }),
+                },
+                None => json!({ "verified": false, "line": line, "message": "there is no instruction there" }),
+            }
+        }).collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program has been launched")?;
+        let pc = session.debugger.cpu().pc();
+        let source = session.debugger.source_map().get(pc.0);
+        // The label the instruction is under, if it doesn't have one of its own
+        let label = session.debugger.labels().name_of(&pc.clone().into(), true).or(source.and_then(|s| s.label.as_deref()));
+        let name = match label {
+            Some(label) => format!("{label} ({pc})"),
+            None => pc.to_string(),
+        };
+        let mut frame = json!({ "id": FRAME, "name": name, "line": 0, "column": 0 });
+        if let Some(source) = source {
+            frame["line"] = json!(source.line);
+            frame["column"] = json!(source.column);
+            frame["source"] = json!({ "path": session.path });
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&self, reference: i64) -> Result<Value, String> {
+        let session = self.session.as_ref().ok_or("no program has been launched")?;
+        let cpu = session.debugger.cpu();
+        let mut labels: Vec<_> = session.debugger.labels().data.iter().collect();
+        labels.sort_by_key(|(name, addr)| (addr.0, name.as_str()));
+        let variables: Vec<_> = match reference {
+            REGISTERS_SCOPE => (0..8).map(|i| (format!("R{i}"), word(Some(cpu.register(i))), 0))
+                .chain((0..8).map(|i| (format!("S{i}"), word(Some(cpu.system_register(i))), 0)))
+                .chain([("PC".to_string(), cpu.pc().to_string(), 0)])
+                .collect(),
+            MEMORY_SCOPE => labels.iter().enumerate()
+                .map(|(i, (name, addr))| (name.to_string(), word(cpu.memory().get_word(addr)), LABEL_REFERENCES + i as i64))
+                .collect(),
+            IO_SCOPE => cpu.io_ports().into_iter().map(|(port, value)| (format!("port {port}"), value.to_string(), 0)).collect(),
+            reference => {
+                let (_, addr) = labels.get((reference - LABEL_REFERENCES) as usize).ok_or("unknown variables reference")?;
+                (0..LABEL_BYTES).map(|i| {
+                    let addr = MemAddr(addr.0.wrapping_add(i));
+                    let value = cpu.memory().get_byte(&addr).map(|b| format!("0x{b:0>2X}")).unwrap_or("--".into());
+                    (ProgCounter(addr.0 as u16).to_string(), value, 0)
+                }).collect()
+            },
+        };
+        let variables: Vec<_> = variables.into_iter()
+            .map(|(name, value, reference)| json!({ "name": name, "value": value, "variablesReference": reference }))
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// The events that tell the editor why the program stopped, after the values written with `OUT`
+    /// since there were `outputs_before` of them
+    fn stop_events(&self, stop: Stop, outputs_before: usize) -> Vec<Value> {
+        let Some(session) = &self.session else { return Vec::new() };
+        let log = session.debugger.cpu().output_log();
+        let mut events: Vec<_> = log[outputs_before.min(log.len())..].iter()
+            .map(|r| output(&format!("[OUTPUT]: {} on port {}\n", r.value, r.port)))
+            .collect();
+        match stop {
+            Stop::Halted(halt) => {
+                events.push(output(&format!("{halt}\n")));
+                events.push(event("exited", json!({ "exitCode": halt.exit_code() })));
+                events.push(event("terminated", json!({})));
+            },
+            Stop::Breakpoint { error, .. } => {
+                events.extend(condition_failure(&error).map(|e| output(&format!("{e}\n"))));
+                events.push(stopped("breakpoint", condition_failure(&error)));
+            },
+            Stop::Watchpoint { what, error, .. } => {
+                events.extend(condition_failure(&error).map(|e| output(&format!("{e}\n"))));
+                events.push(stopped("data breakpoint", Some(what)));
+            },
+            Stop::Done => events.push(stopped("step", None)),
+            Stop::Start => events.push(stopped("step", Some("reached the first instruction remembered".into()))),
+            Stop::Limit => events.push(stopped("pause", Some("stopped after too many instructions".into()))),
+        }
+        events
+    }
+}
+
+fn event(name: &str, body: Value) -> Value {
+    json!({ "type": "event", "event": name, "body": body })
+}
+
+fn stopped(reason: &str, description: Option<String>) -> Value {
+    let mut body = json!({ "reason": reason, "threadId": THREAD, "allThreadsStopped": true });
+    if let Some(description) = description { body["description"] = json!(description); }
+    event("stopped", body)
+}
+
+fn output(text: &str) -> Value {
+    event("output", json!({ "category": "stdout", "output": text }))
+}
+
+fn word(value: Option<i16>) -> String {
+    match value {
+        Some(v) => format!("0x{v:0>4X} ({v})"),
+        None => "--".into(),
+    }
+}
+
+/// Read the next message: a `Content-Length` header, an empty line and that many bytes of JSON.
+/// Returns `None` once the input is over
+fn read_message(input: &mut impl BufRead) -> anyhow::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 { return Ok(None); }
+        let line = line.trim();
+        if line.is_empty() && length.is_some() { break; }
+        if let Some(n) = line.strip_prefix("Content-Length:") { length = Some(n.trim().parse::<usize>()?); }
+    }
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[test]
+fn test_dap_session() {
+    use crate::{read_instructions, Processador, Registers, Memory, SourceMap, SourceLocation};
+    use std::collections::HashMap;
+
+    crate::set_quiet(true);
+    let mut server = DapServer::new(Box::new(|_| {
+        let mut debugger = Debugger::new(Box::new(|| {
+            let instructions = read_instructions("MOVI R1, 0x05\nOUT 0x02, R1\nADDI R1, R1, 1")?;
+            Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+        }))?;
+        let mut source_map = SourceMap::new();
+        for (pc, line, text) in [(0, 3, "MOVI R1, 5"), (2, 5, "OUT 2, R1"), (4, 6, "ADDI R1, R1, 1")] {
+            source_map.insert(pc, SourceLocation { file: "x.sisa".into(), line, column: 2, text: text.into(), label: Some("main".into()) });
+        }
+        debugger.set_source_map(source_map);
+        Ok(debugger)
+    }));
+    let mut request = |command: &str, arguments: Value| server.handle(&json!({ "seq": 1, "command": command, "arguments": arguments }));
+
+    // Breakpoints can't be set until there's a program, so the editor is only told to send them then
+    assert_eq!(request("initialize", json!({})).len(), 1);
+    let launched = request("launch", json!({ "program": "x.sisa", "stopOnEntry": true }));
+    assert_eq!((&launched[0]["success"], &launched[1]["event"]), (&json!(true), &json!("initialized")));
+    let breakpoints = request("setBreakpoints", json!({ "breakpoints": [{ "line": 4 }, { "line": 9 }] }));
+    assert_eq!(breakpoints[0]["body"]["breakpoints"][0]["line"], 5);
+    assert_eq!(breakpoints[0]["body"]["breakpoints"][1]["verified"], false);
+    assert_eq!(request("configurationDone", json!({}))[1]["body"]["reason"], "entry");
+
+    let stop = request("continue", json!({}));
+    assert_eq!(stop[1]["body"]["reason"], "breakpoint");
+    let frame = &request("stackTrace", json!({}))[0]["body"]["stackFrames"][0];
+    assert_eq!((&frame["line"], &frame["column"], &frame["name"]), (&json!(5), &json!(2), &json!("main (0x0002)")));
+    let variables = request("variables", json!({ "variablesReference": REGISTERS_SCOPE }));
+    assert_eq!(variables[0]["body"]["variables"][1]["value"], "0x0005 (5)");
+
+    let stop = request("continue", json!({}));
+    assert_eq!(stop[1]["event"], "output");
+    assert_eq!(stop.last().unwrap()["event"], "terminated");
+}
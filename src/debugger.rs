@@ -0,0 +1,745 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::{execute::{Processador, Halt, MemAddr, ProgCounter}, expression::{Context, ExprError, Expression}, spec::Instruction, preprocessor::Labels, source_map::SourceMap, watchpoint::Watchpoint, parse_value};
+
+const HELP: &str = "\
+step [n]           run the next n instructions (1 by default)
+next               like step, but runs a whole JALR call at once
+continue           run until the program halts or reaches a breakpoint
+step-back [n]      undo the last n instructions (1 by default)
+reverse-continue   undo instructions until a breakpoint or watchpoint is reached, backwards
+goto <step>        go back (or forward) to when step instructions had been executed
+break <addr|label> stop before running the instruction there. Add 'if <expr>' to stop only when
+                   an expression holds, like 'break loop if R3 == 0 && hits > 5'
+watch <target>     stop when a register (R3), a word (an address or a .data label) or a range
+                   (0x4000-0x400F) changes. Add 'read' or 'write' to stop on any read or write
+                   instead, and a condition on the new value like 'if new == 5' (or just '== 5')
+condition <n> [e]  make breakpoint (or watchpoint) n stop only if e holds, or always without e
+delete <n>         remove breakpoint (or watchpoint) number n
+print <expr>       show the value of an expression, like 'mem[v+4] * 2' or 'io[1] != 0'
+breakpoints        list the breakpoints and watchpoints and how many times they have been hit
+regs               show the registers
+mem <addr> [len]   show len bytes of memory starting at addr (16 by default)
+set R3 0x10        change the value of a register
+set mem 0x4000 5   change the value of a word of memory
+pc                 show the PC and the instruction it points to
+io                 show the IO ports and the values written with OUT
+reset              start the program over
+quit               stop debugging
+An empty line repeats the last command";
+
+/// Why the [Debugger] stopped running the program
+#[derive(Debug)]
+#[allow(missing_docs)]
+pub enum Stop {
+    /// It ran as many instructions as it was asked to
+    Done,
+    /// The program halted, and won't go any further
+    Halted(Halt),
+    /// The program reached a [Breakpoint]: the one with this `id`, which has been hit `hits` times.
+    /// `error` says why its condition couldn't be evaluated, if it couldn't
+    Breakpoint { id: usize, hits: usize, error: Option<ExprError> },
+    /// The last instruction, at `pc`, triggered a [Watchpoint], which has been hit `hits` times.
+    /// `what` describes what it did, and `error` is as for a [Breakpoint](Stop::Breakpoint)
+    Watchpoint { id: usize, hits: usize, pc: u16, what: String, error: Option<ExprError> },
+    /// Going backwards, it reached the first instruction it remembers
+    Start,
+    /// It ran [MAX_INSTRUCTION_RUN_SIZE](Processador::MAX_INSTRUCTION_RUN_SIZE) instructions
+    /// without stopping
+    Limit,
+}
+
+/// Errors in the commands given to the [Debugger]
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum DebugError {
+    #[error("unknown command '{0}' (try 'help')")]
+    UnknownCommand(String),
+    #[error("missing {0}")]
+    MissingArgument(&'static str),
+    #[error("could not understand '{0}' as a number (decimal, hex or 'c')")]
+    InvalidValue(String),
+    #[error("could not understand '{0}' as a number of instructions")]
+    InvalidCount(String),
+    #[error("unknown register '{0}' (expected R0 to R7)")]
+    InvalidRegister(String),
+    #[error("could not start the program over: {0}")]
+    Reset(anyhow::Error),
+    #[error("'{0}' is neither an address nor a label")]
+    UnknownLocation(String),
+    #[error("there is no breakpoint number {0}")]
+    UnknownBreakpoint(usize),
+    #[error("could not understand the condition '{0}' (expected something like '== 5')")]
+    InvalidCondition(String),
+    #[error("the range '{0}' ends before it starts")]
+    InvalidRange(String),
+    #[error("invalid expression: {0}")]
+    Expression(#[from] ExprError),
+}
+
+/// A place to stop at, before running the instruction there
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    /// Number that identifies it
+    pub id: usize,
+    /// Address of the instruction
+    pub pc: u16,
+    /// Only stop if this holds
+    pub condition: Option<Expression>,
+    /// Times the program has reached it (even if its condition didn't hold)
+    pub hits: usize,
+}
+
+/// Runs a [Processador] step by step, as told by commands like `step`, `continue` or `mem 0x4000`
+/// (see [command](Debugger::command)), instead of all at once.
+///
+/// To be able to start the program over, the debugger is given a function that sets it up.
+pub struct Debugger {
+    cpu: Processador,
+    setup: Box<dyn FnMut() -> anyhow::Result<Processador>>,
+    last_command: String,
+    labels: Labels,
+    source_map: SourceMap,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_breakpoint: usize,
+    exit_code: i32,
+    /// Where each `JALR` call that hasn't returned yet will return to, innermost last
+    calls: Vec<u16>,
+}
+
+impl Debugger {
+    /// Debug the program that `setup` creates
+    pub fn new(mut setup: Box<dyn FnMut() -> anyhow::Result<Processador>>) -> anyhow::Result<Self> {
+        let mut cpu = setup()?;
+        cpu.set_journal(true);
+        Ok(Self {
+            cpu,
+            setup,
+            last_command: String::new(),
+            labels: Labels::default(),
+            source_map: SourceMap::new(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_breakpoint: 1,
+            exit_code: 0,
+            calls: Vec::new(),
+        })
+    }
+
+    /// Let breakpoints (and other commands) use the program's labels
+    pub fn set_labels(&mut self, labels: Labels) { self.labels = labels; }
+
+    /// The program's labels
+    pub fn labels(&self) -> &Labels { &self.labels }
+
+    /// Show the instructions as they were written in the source file
+    pub fn set_source_map(&mut self, source_map: SourceMap) { self.source_map = source_map; }
+
+    /// Where in the source file each instruction comes from (empty if it isn't known)
+    pub fn source_map(&self) -> &SourceMap { &self.source_map }
+
+    /// Exit code of the last time the program halted (see [Halt::exit_code]), or 0 if it hasn't
+    /// since it was started
+    pub fn exit_code(&self) -> i32 { self.exit_code }
+
+    /// Stop before running the instruction at `location`, an address or a label of the .text
+    /// section. Returns the id of the new breakpoint
+    pub fn add_breakpoint(&mut self, location: &str) -> Result<usize, DebugError> {
+        let pc = match self.labels.text.get(location) {
+            Some(addr) => addr.0 as u16,
+            None => parse_value(location).ok_or_else(|| DebugError::UnknownLocation(location.into()))? as u16,
+        };
+        let id = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints.push(Breakpoint { id, pc, condition: None, hits: 0 });
+        Ok(id)
+    }
+
+    /// Stop when a register or some memory is read, written or changed, as given to the `watch`
+    /// command (see [Watchpoint::parse]). Returns the id of the new watchpoint
+    pub fn add_watchpoint(&mut self, args: &[&str]) -> Result<usize, DebugError> {
+        let watchpoint = Watchpoint::parse(self.next_breakpoint, args, &self.labels)?;
+        self.next_breakpoint += 1;
+        self.watchpoints.push(watchpoint);
+        Ok(self.next_breakpoint - 1)
+    }
+
+    /// Remove the breakpoint or watchpoint with the given id
+    pub fn remove_breakpoint(&mut self, id: usize) -> Result<(), DebugError> {
+        if let Some(idx) = self.breakpoints.iter().position(|b| b.id == id) {
+            self.breakpoints.remove(idx);
+        } else {
+            let idx = self.watchpoints.iter().position(|w| w.id == id).ok_or(DebugError::UnknownBreakpoint(id))?;
+            self.watchpoints.remove(idx);
+        }
+        Ok(())
+    }
+
+    /// Make the breakpoint or watchpoint with the given id stop only when `condition` (an
+    /// [Expression]) holds, or always if there is none
+    pub fn set_condition(&mut self, id: usize, condition: Option<&str>) -> Result<(), DebugError> {
+        let condition = condition.map(|c| Expression::parse(c, &self.labels)).transpose()?;
+        if let Some(b) = self.breakpoints.iter_mut().find(|b| b.id == id) {
+            b.condition = condition;
+        } else {
+            self.watchpoints.iter_mut().find(|w| w.id == id).ok_or(DebugError::UnknownBreakpoint(id))?.condition = condition;
+        }
+        Ok(())
+    }
+
+    /// Every breakpoint, in the order they were added
+    pub fn breakpoints(&self) -> &[Breakpoint] { &self.breakpoints }
+
+    /// Every watchpoint, in the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] { &self.watchpoints }
+
+    /// Count the hits of the watchpoints the last instruction (at `pc`) triggered, and stop at the
+    /// first one whose condition holds
+    fn check_watchpoints(&mut self, pc: u16) -> Option<Stop> {
+        for w in &mut self.watchpoints {
+            if w.check(&self.cpu).is_some() { w.hits += 1; }
+        }
+        self.watchpoint_stop(pc)
+    }
+
+    /// The first watchpoint that the last instruction (at `pc`) triggered and whose condition
+    /// holds, without counting it as a hit (as when going backwards)
+    fn watchpoint_stop(&self, pc: u16) -> Option<Stop> {
+        self.watchpoints.iter().find_map(|w| {
+            let (what, value) = w.check(&self.cpu)?;
+            match holds(&w.condition, &self.cpu, w.hits, Some(value)) {
+                Ok(false) => None,
+                held => Some(Stop::Watchpoint { id: w.id, hits: w.hits, pc, what, error: held.err() }),
+            }
+        })
+    }
+
+    /// Count the hits of the breakpoints the program is at, and stop at the first one whose
+    /// condition holds
+    fn check_breakpoints(&mut self) -> Option<Stop> {
+        let pc = self.cpu.pc().0;
+        self.breakpoints.iter_mut().filter(|b| b.pc == pc).for_each(|b| b.hits += 1);
+        self.breakpoint_stop()
+    }
+
+    /// The first breakpoint the program is at whose condition holds, without counting it as a hit
+    fn breakpoint_stop(&self) -> Option<Stop> {
+        self.breakpoints.iter()
+            .filter(|b| b.pc == self.cpu.pc().0)
+            .find_map(|b| match holds(&b.condition, &self.cpu, b.hits, None) {
+                Ok(false) => None,
+                held => Some(Stop::Breakpoint { id: b.id, hits: b.hits, error: held.err() }),
+            })
+    }
+
+    /// The processor being debugged
+    pub fn cpu(&self) -> &Processador { &self.cpu }
+
+    /// The processor being debugged, to change its state
+    pub fn cpu_mut(&mut self) -> &mut Processador { &mut self.cpu }
+
+    /// Run (at most) `n` instructions
+    pub fn step(&mut self, n: usize) -> Stop {
+        let mut left = n;
+        self.run_until(|_| { left = left.saturating_sub(1); left == 0 })
+    }
+
+    /// Calls (made with `JALR`) that haven't returned yet. A `JALR` to the instruction after the
+    /// innermost one returns from it, and any other is a call
+    pub fn call_depth(&self) -> usize { self.calls.len() }
+
+    /// Run the next instruction. If it's a `JALR`, keep going until it returns to the instruction
+    /// after it
+    pub fn step_over(&mut self) -> Stop {
+        let pc = self.cpu.pc().clone();
+        match self.cpu.instruction_at(&pc) {
+            Some(Instruction::JALR { .. }) => self.run_until(|d| d.cpu.pc().0 == pc.0.wrapping_add(2)),
+            _ => self.step(1),
+        }
+    }
+
+    /// Run until the current call returns (see [call_depth](Debugger::call_depth)). Outside of any
+    /// call, that's until the program stops
+    pub fn step_out(&mut self) -> Stop {
+        let depth = self.calls.len();
+        self.run_until(|d| d.calls.len() < depth)
+    }
+
+    /// Run until the program halts
+    pub fn cont(&mut self) -> Stop { self.run_until(|_| false) }
+
+    /// Undo (at most) `n` instructions
+    pub fn step_back(&mut self, n: usize) -> Stop {
+        for _ in 0..n {
+            if !self.undo() { return Stop::Start; }
+        }
+        Stop::Done
+    }
+
+    /// Undo instructions until one that triggers a watchpoint has been undone, or the PC reaches a
+    /// breakpoint. The hits were already counted on the way forward, so they aren't counted again
+    pub fn reverse_cont(&mut self) -> Stop {
+        loop {
+            let Some(pc) = self.cpu.last_pc().map(|pc| pc.0) else { return Stop::Start };
+            // Watchpoints look at the state right after the instruction
+            let watched = self.watchpoint_stop(pc);
+            if !self.undo() { return Stop::Start; }
+            if let Some(stop) = watched.or_else(|| self.breakpoint_stop()) { return stop; }
+        }
+    }
+
+    /// Go back (or forward, ignoring breakpoints) to when `step` instructions had been executed
+    pub fn goto(&mut self, step: usize) -> Stop {
+        while self.cpu.instruction_count().total() > step {
+            if !self.undo() { return Stop::Start; }
+        }
+        while self.cpu.instruction_count().total() < step {
+            if let Err(halt) = self.execute() { return self.halted(halt); }
+        }
+        Stop::Done
+    }
+
+    /// Start the program over. The breakpoints are kept. If the program starts at one, it's hit
+    /// right away
+    pub fn reset(&mut self) -> anyhow::Result<Option<Stop>> {
+        self.cpu = (self.setup)()?;
+        self.cpu.set_journal(true);
+        self.exit_code = 0;
+        self.calls.clear();
+        Ok(self.check_breakpoints())
+    }
+
+    fn halted(&mut self, halt: Halt) -> Stop {
+        self.exit_code = halt.exit_code();
+        Stop::Halted(halt)
+    }
+
+    /// Run instructions until `done` says so (it's checked after each one), a breakpoint or
+    /// watchpoint is reached, the program halts, or the instruction limit is reached
+    fn run_until(&mut self, mut done: impl FnMut(&Self) -> bool) -> Stop {
+        for _ in 0..Processador::MAX_INSTRUCTION_RUN_SIZE {
+            let pc = self.cpu.pc().0;
+            if let Err(halt) = self.execute() { return self.halted(halt); }
+            if let Some(stop) = self.check_watchpoints(pc) { return stop; }
+            if let Some(stop) = self.check_breakpoints() { return stop; }
+            if done(self) { return Stop::Done; }
+        }
+        Stop::Limit
+    }
+
+    /// Run the next instruction, keeping track of the calls
+    fn execute(&mut self) -> Result<(), Halt> {
+        let pc = self.cpu.pc().0;
+        let jump = matches!(self.cpu.instruction_at(&ProgCounter(pc)), Some(Instruction::JALR { .. }));
+        self.cpu.execute_next(false)?;
+        if jump {
+            match self.calls.last() {
+                Some(&back) if back == self.cpu.pc().0 => { self.calls.pop(); },
+                _ => self.calls.push(pc.wrapping_add(2)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Undo the last instruction, and the call it made or returned from. Returns whether there was
+    /// one to undo
+    fn undo(&mut self) -> bool {
+        let Some(pc) = self.cpu.last_pc().map(|pc| pc.0) else { return false };
+        let after = self.cpu.pc().0;
+        if !self.cpu.step_back() { return false; }
+        if let Some(Instruction::JALR { .. }) = self.cpu.instruction_at(&ProgCounter(pc)) {
+            match self.calls.last() {
+                Some(&back) if back == pc.wrapping_add(2) => { self.calls.pop(); },
+                _ => self.calls.push(after),
+            }
+        }
+        true
+    }
+
+    /// Where the program is: the PC and the instruction there
+    fn location(&self) -> String {
+        let pc = self.cpu.pc();
+        let label = match self.labels.name_of(&pc.clone().into(), true) {
+            Some(label) => format!(" ({label})"),
+            None => String::new(),
+        };
+        format!("PC = {}", self.instruction(pc, &label))
+    }
+
+    /// The instruction at `pc`, as it was written in the source file if it's known
+    fn instruction(&self, pc: &ProgCounter, label: &str) -> String {
+        match (self.cpu.instruction_at(pc), self.source_map.get(pc.0)) {
+            (None, _) => format!("{pc}{label}: no instruction"),
+            (Some(_), Some(source)) => format!("{pc}{label} at {source}"),
+            (Some(inst), None) => format!("{pc}{label}: {inst}"),
+        }
+    }
+
+    fn describe_stop(&self, stop: Stop, outputs_before: usize) -> String {
+        let log = self.cpu.output_log();
+        let mut out: String = log[outputs_before.min(log.len())..].iter()
+            .map(|r| format!("[OUTPUT]: {} on port {}\n", r.value, r.port))
+            .collect();
+        match stop {
+            Stop::Done => {},
+            Stop::Halted(halt) => out.push_str(&format!("{halt}\n")),
+            Stop::Breakpoint { id, hits, error } => {
+                out.push_str(&format!("Breakpoint {id} hit ({})\n", times(hits)));
+                out.extend(condition_failure(&error).map(|e| format!("{e}\n")));
+            },
+            Stop::Watchpoint { id, hits, pc, what, error } => {
+                let inst = self.instruction(&ProgCounter(pc), "");
+                out.push_str(&format!("Watchpoint {id} hit ({}): {what}\n  by {inst}\n", times(hits)));
+                out.extend(condition_failure(&error).map(|e| format!("{e}\n")));
+            },
+            Stop::Start => out.push_str("reached the first instruction remembered\n"),
+            Stop::Limit => out.push_str(&format!("stopped after {} instructions\n", Processador::MAX_INSTRUCTION_RUN_SIZE)),
+        }
+        out.push_str(&self.location());
+        out
+    }
+
+    fn registers(&self) -> String {
+        let line = |prefix, get: &dyn Fn(u8) -> i16| (0..8)
+            .map(|i| format!("{prefix}{i} = 0x{:0>4X} ({})", get(i), get(i)))
+            .collect::<Vec<_>>().join("  ");
+        format!("{}\n{}", line("R", &|i| self.cpu.register(i)), line("S", &|i| self.cpu.system_register(i)))
+    }
+
+    fn memory(&self, start: i16, len: usize) -> String {
+        let mut out = String::new();
+        for row in 0..len.div_ceil(16) {
+            let addr = start.wrapping_add((row * 16) as i16);
+            out.push_str(&format!("0x{addr:0>4X}:"));
+            for i in 0..16.min(len - row * 16) {
+                match self.cpu.memory().get_byte(&MemAddr(addr.wrapping_add(i as i16))) {
+                    Some(b) => out.push_str(&format!(" {b:0>2X}")),
+                    None => out.push_str(" --"),
+                }
+            }
+            out.push('\n');
+        }
+        out.trim_end().into()
+    }
+
+    fn io(&self) -> String {
+        let mut out = String::from("Ports:");
+        for (port, value) in self.cpu.io_ports() {
+            out.push_str(&format!("\n  {port}: {value}"));
+        }
+        out.push_str("\nWritten with OUT:");
+        for r in self.cpu.output_log() {
+            out.push_str(&format!("\n  step {}: {} on port {}", r.step, r.value, r.port));
+        }
+        out
+    }
+
+    /// Run a single command, returning what it has to say, or `None` if debugging is over. An empty
+    /// command repeats the last one
+    pub fn command(&mut self, line: &str) -> Result<Option<String>, DebugError> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command = line.clone();
+        let mut parts = line.split_whitespace();
+        let Some(command) = parts.next() else { return Ok(Some(String::new())) };
+        let outputs_before = self.cpu.output_log().len();
+
+        Ok(Some(match command {
+            "step" | "s" => {
+                let n = parts.next().map(count).transpose()?.unwrap_or(1);
+                let stop = self.step(n.max(1));
+                self.describe_stop(stop, outputs_before)
+            },
+            "next" | "n" => {
+                let stop = self.step_over();
+                self.describe_stop(stop, outputs_before)
+            },
+            "continue" | "c" => {
+                let stop = self.cont();
+                self.describe_stop(stop, outputs_before)
+            },
+            "step-back" | "sb" => {
+                let n = parts.next().map(count).transpose()?.unwrap_or(1);
+                let stop = self.step_back(n.max(1));
+                format!("Step {}\n{}", self.cpu.instruction_count().total(), self.describe_stop(stop, usize::MAX))
+            },
+            "reverse-continue" | "rc" => {
+                let stop = self.reverse_cont();
+                format!("Step {}\n{}", self.cpu.instruction_count().total(), self.describe_stop(stop, usize::MAX))
+            },
+            "goto" => {
+                let step = count(parts.next().ok_or(DebugError::MissingArgument("step"))?)?;
+                let stop = self.goto(step);
+                format!("Step {}\n{}", self.cpu.instruction_count().total(), self.describe_stop(stop, outputs_before))
+            },
+            "regs" | "r" => self.registers(),
+            "mem" | "m" => {
+                let addr = value(parts.next().ok_or(DebugError::MissingArgument("address"))?)?;
+                let len = parts.next().map(value).transpose()?.unwrap_or(16);
+                self.memory(addr, len.max(1) as u16 as usize)
+            },
+            "set" => {
+                let target = parts.next().ok_or(DebugError::MissingArgument("register or 'mem'"))?;
+                if target == "mem" {
+                    let addr = value(parts.next().ok_or(DebugError::MissingArgument("address"))?)?;
+                    let v = value(parts.next().ok_or(DebugError::MissingArgument("value"))?)?;
+                    self.cpu.memory_mut().insert_word(&MemAddr(addr), v);
+                    self.memory(addr & !1, 2)
+                } else {
+                    let reg = register(target)?;
+                    let v = value(parts.next().ok_or(DebugError::MissingArgument("value"))?)?;
+                    self.cpu.set_register(reg, v);
+                    format!("R{reg} = 0x{v:0>4X} ({v})")
+                }
+            },
+            "pc" => self.location(),
+            "io" => self.io(),
+            "break" | "b" => {
+                let location = parts.next().ok_or(DebugError::MissingArgument("address or label"))?;
+                let condition = match parts.next() {
+                    Some("if") => Some(Expression::parse(&parts.collect::<Vec<_>>().join(" "), &self.labels)?),
+                    Some(other) => return Err(DebugError::InvalidCondition(other.into())),
+                    None => None,
+                };
+                let id = self.add_breakpoint(location)?;
+                let b = self.breakpoints.last_mut().unwrap();
+                b.condition = condition;
+                format!("Breakpoint {id} at {}{}", ProgCounter(b.pc), condition_text(&b.condition))
+            },
+            "condition" => {
+                let id = value(parts.next().ok_or(DebugError::MissingArgument("breakpoint number"))?)?;
+                let condition = parts.collect::<Vec<_>>().join(" ");
+                self.set_condition(id as usize, Some(condition.as_str()).filter(|c| !c.is_empty()))?;
+                if condition.is_empty() { format!("Breakpoint {id} is now unconditional") } else { format!("Breakpoint {id} stops if {condition}") }
+            },
+            "print" | "p" => {
+                let expr = Expression::parse(&parts.collect::<Vec<_>>().join(" "), &self.labels)?;
+                let v = expr.eval(&Context { cpu: &self.cpu, hits: 0, value: None })?;
+                format!("{expr} = 0x{v:0>4X} ({v})")
+            },
+            "watch" | "w" => {
+                let args: Vec<_> = parts.collect();
+                let id = self.add_watchpoint(&args)?;
+                format!("Watchpoint {id} on {}", args[0])
+            },
+            "delete" | "d" => {
+                let id = value(parts.next().ok_or(DebugError::MissingArgument("breakpoint number"))?)?;
+                self.remove_breakpoint(id as usize)?;
+                format!("Removed breakpoint {id}")
+            },
+            "breakpoints" => {
+                let mut list: Vec<_> = self.breakpoints.iter().map(|b| {
+                    let label = self.labels.name_of(&MemAddr(b.pc as i16), true).map(|l| format!(" ({l})")).unwrap_or_default();
+                    (b.id, format!("{}: {}{label}{}, hit {}", b.id, ProgCounter(b.pc), condition_text(&b.condition), times(b.hits)))
+                }).chain(self.watchpoints.iter().map(|w| {
+                    (w.id, format!("{}: watch {} ({}){}, hit {}", w.id, w.name, w.kind, condition_text(&w.condition), times(w.hits)))
+                })).collect();
+                list.sort_by_key(|(id, _)| *id);
+                if list.is_empty() { "No breakpoints".into() } else { list.into_iter().map(|(_, l)| l).collect::<Vec<_>>().join("\n") }
+            },
+            "reset" => {
+                let stop = self.reset().map_err(DebugError::Reset)?;
+                format!("Started over\n{}", self.describe_stop(stop.unwrap_or(Stop::Done), 0))
+            },
+            "help" | "h" => HELP.into(),
+            "quit" | "q" => return Ok(None),
+            other => return Err(DebugError::UnknownCommand(other.into())),
+        }))
+    }
+
+    /// Read commands from stdin and run them until `quit` (or the end of the input)
+    pub fn repl(&mut self) -> anyhow::Result<()> {
+        let interactive = io::stdin().is_terminal();
+        let stop = self.check_breakpoints().unwrap_or(Stop::Done);
+        println!("{}", self.describe_stop(stop, 0));
+        loop {
+            if interactive {
+                print!("(sisa) ");
+                io::stdout().flush()?;
+            }
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 { return Ok(()); }
+            match self.command(&line) {
+                Ok(Some(out)) => println!("{out}"),
+                Ok(None) => return Ok(()),
+                Err(e) => println!("[DEBUG]: {e}"),
+            }
+        }
+    }
+}
+
+/// Whether the condition of a breakpoint (or watchpoint, whose target was accessed with `value`)
+/// hit `hits` times holds. One that can't be evaluated, like a division by zero, stops the program
+/// too, so that it can be looked into
+fn holds(condition: &Option<Expression>, cpu: &Processador, hits: usize, value: Option<i16>) -> Result<bool, ExprError> {
+    condition.as_ref().map_or(Ok(true), |c| c.holds(&Context { cpu, hits, value }))
+}
+
+/// Why the condition of a stop couldn't be evaluated, if it couldn't
+pub(crate) fn condition_failure(error: &Option<ExprError>) -> Option<String> {
+    error.as_ref().map(|e| format!("condition could not be evaluated: {e}"))
+}
+
+fn condition_text(condition: &Option<Expression>) -> String {
+    condition.as_ref().map(|c| format!(" if {c}")).unwrap_or_default()
+}
+
+fn times(n: usize) -> String {
+    if n == 1 { "1 time".into() } else { format!("{n} times") }
+}
+
+/// A number given in a command: decimal, hex or a character
+pub(crate) fn value(input: &str) -> Result<i16, DebugError> {
+    parse_value(input).ok_or_else(|| DebugError::InvalidValue(input.into()))
+}
+
+/// A number of instructions given in a command, in decimal
+fn count(input: &str) -> Result<usize, DebugError> {
+    input.parse().map_err(|_| DebugError::InvalidCount(input.into()))
+}
+
+pub(crate) fn register(input: &str) -> Result<u8, DebugError> {
+    input.strip_prefix(['R', 'r'])
+        .and_then(|n| n.parse::<u8>().ok())
+        .filter(|n| *n < 8)
+        .ok_or_else(|| DebugError::InvalidRegister(input.into()))
+}
+
+#[test]
+fn test_debugger_commands() {
+    use crate::{read_instructions, Registers, Memory};
+    use std::collections::HashMap;
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("MOVI R1, 0x05\nADDI R1, R1, 1\nADDI R1, R1, 1")?;
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    crate::set_quiet(true);
+    debugger.command("step 2").unwrap();
+    assert_eq!(debugger.cpu().register(1), 6);
+    debugger.command("set R1 0x10").unwrap();
+    debugger.command("").unwrap();
+    assert_eq!(debugger.cpu().register(1), 0x10);
+    debugger.command("set mem 0x4000 0x1234").unwrap();
+    assert_eq!(debugger.command("mem 0x4000 2").unwrap().unwrap(), "0x4000: 34 12");
+    assert!(matches!(debugger.cont(), Stop::Halted(Halt::NoInstruction { .. })));
+    assert_eq!(debugger.cpu().register(1), 0x11);
+    debugger.command("reset").unwrap();
+    assert_eq!(debugger.cpu().pc().0, 0);
+    assert!(debugger.command("frobnicate").is_err());
+    assert!(debugger.command("quit").unwrap().is_none());
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions("MOVI R1, 1\nBNZ R1, -2")?, HashMap::new()))
+    })).unwrap();
+    assert!(matches!(debugger.cont(), Stop::Halted(Halt::NonTerminatingLoop { .. })));
+    assert_eq!(debugger.exit_code(), 2);
+    debugger.reset().unwrap();
+    assert_eq!(debugger.exit_code(), 0);
+}
+
+#[test]
+fn test_breakpoints() {
+    use crate::{read_instructions, Registers, Memory};
+    use std::collections::HashMap;
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("MOVI R1, 0x00\nADDI R1, R1, 1\nBZ R0, -2")?;
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    let mut labels = Labels::default();
+    labels.text.insert("loop".into(), MemAddr(2));
+    debugger.set_labels(labels);
+    crate::set_quiet(true);
+    assert_eq!(debugger.add_breakpoint("loop").unwrap(), 1);
+    assert!(debugger.add_breakpoint("nowhere").is_err());
+    assert!(matches!(debugger.cont(), Stop::Breakpoint { id: 1, hits: 1, .. }));
+    assert!(matches!(debugger.cont(), Stop::Breakpoint { id: 1, hits: 2, .. }));
+    assert_eq!(debugger.cpu().register(1), 1);
+    debugger.command("delete 1").unwrap();
+    assert!(debugger.breakpoints().is_empty());
+    assert!(debugger.command("delete 1").is_err());
+
+    debugger.command("break loop if R1 % 3 == 0 && hits > 2").unwrap();
+    assert!(matches!(debugger.cont(), Stop::Breakpoint { id: 2, hits: 5, .. }));
+    assert_eq!(debugger.cpu().register(1), 6);
+    assert_eq!(debugger.command("print R1 * 2 + loop").unwrap().unwrap(), "R1 * 2 + loop = 0x000E (14)");
+    debugger.command("condition 2 hits == 7").unwrap();
+    assert!(matches!(debugger.cont(), Stop::Breakpoint { id: 2, hits: 7, .. }));
+    assert!(debugger.command("condition 2 R1 >").is_err());
+    assert!(debugger.command("break loop when R1").is_err());
+    debugger.command("condition 2 R1 / (R1 - 10) == 100").unwrap();
+    assert!(matches!(debugger.cont(), Stop::Breakpoint { id: 2, error: Some(ExprError::DivisionByZero), .. }));
+    assert_eq!(debugger.cpu().register(1), 10);
+}
+
+#[test]
+fn test_step_out() {
+    use crate::{read_instructions, execute::RegLabel, Registers, Memory};
+    use std::collections::HashMap;
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        // The text format has no JALR: 0x0002 calls 0x000A, which returns at 0x000C
+        let mut instructions = read_instructions("MOVI R5, 10\nNOP\nADDI R1, R1, 1\nBZ R2, 20\nNOP\nADDI R3, R3, 1")?;
+        instructions.insert(MemAddr(2), Instruction::JALR { a: RegLabel(5), d: RegLabel(6) });
+        instructions.insert(MemAddr(12), Instruction::JALR { a: RegLabel(6), d: RegLabel(0) });
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    crate::set_quiet(true);
+    debugger.step(2);
+    assert_eq!((debugger.cpu().pc().0, debugger.call_depth()), (10, 1));
+    assert!(matches!(debugger.step_out(), Stop::Done));
+    assert_eq!((debugger.cpu().pc().0, debugger.call_depth(), debugger.cpu().register(3)), (4, 0, 1));
+    // Going backwards undoes the return, and then the call
+    debugger.step_back(1);
+    assert_eq!((debugger.cpu().pc().0, debugger.call_depth()), (12, 1));
+    debugger.step_back(2);
+    assert_eq!((debugger.cpu().pc().0, debugger.call_depth()), (2, 0));
+    // Outside of any call, it runs until the program stops
+    assert!(matches!(debugger.step_out(), Stop::Halted(_)));
+}
+
+#[test]
+fn test_reverse_execution() {
+    use crate::{read_instructions, Registers, Memory};
+    use std::collections::HashMap;
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("MOVI R1, 0x10\nIN R2, 0x03\nST 0x00(R1), R2\nOUT 0x05, R2\nADDI R2, R2, 1")?;
+        let io = HashMap::from([(MemAddr(3), crate::Value16Bit(7))]);
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, io))
+    })).unwrap();
+    crate::set_quiet(true);
+    debugger.step(5);
+    assert_eq!(debugger.cpu().register(2), 8);
+    assert_eq!(debugger.cpu().output_log().len(), 1);
+
+    assert!(matches!(debugger.step_back(2), Stop::Done));
+    assert_eq!(debugger.cpu().pc().0, 6);
+    assert_eq!(debugger.cpu().register(2), 7);
+    assert!(debugger.cpu().output_log().is_empty());
+    debugger.goto(1);
+    assert_eq!(debugger.cpu().register(2), 0);
+    assert!(debugger.cpu().memory().get_byte(&MemAddr(0x10)).is_none());
+    assert!(matches!(debugger.step_back(5), Stop::Start));
+
+    debugger.command("watch 0x10").unwrap();
+    assert!(matches!(debugger.cont(), Stop::Watchpoint { pc: 4, .. }));
+    debugger.cont();
+    assert_eq!(debugger.cpu().register(2), 8);
+    // Going backwards doesn't count the hits again
+    assert!(matches!(debugger.reverse_cont(), Stop::Watchpoint { pc: 4, hits: 1, .. }));
+    assert_eq!(debugger.cpu().instruction_count().total(), 2);
+    assert_eq!(debugger.watchpoints()[0].hits, 1);
+    debugger.command("delete 1").unwrap();
+    debugger.command("break 6 if hits == 2").unwrap();
+    debugger.cont();
+    assert!(!matches!(debugger.reverse_cont(), Stop::Breakpoint { .. }));
+    assert_eq!(debugger.breakpoints()[0].hits, 1);
+
+    // Counts aren't limited to 16 bits
+    debugger.command("sb 40000").unwrap();
+    assert_eq!(debugger.cpu().instruction_count().total(), 0);
+    assert!(debugger.command("goto 70000").unwrap().unwrap().contains("Step 5"));
+    assert!(debugger.command("sb -1").is_err());
+}
@@ -0,0 +1,144 @@
+use std::hash::Hasher;
+
+use crate::{io::{Clock, IoDevice, IoError}, execute::{MemAddr, Value16Bit}, is_quiet};
+use super::{IoTimeline, Trigger};
+
+/// The IO ports of the peripherals of the DE1/DE2 board
+#[derive(Debug, Clone)]
+pub struct BoardPorts {
+    /// Green LEDs (output, one bit each)
+    pub green_leds: i16,
+    /// Red LEDs (output, one bit each)
+    pub red_leds: i16,
+    /// Pushbuttons (input, bit `i` is set while `KEY i` is pressed)
+    pub keys: i16,
+    /// Switches (input, bit `i` is set while `SW i` is up)
+    pub switches: i16,
+    /// Which 7-segment displays are on (output, bit `i` turns display `i` on)
+    pub display_control: i16,
+    /// What the 7-segment displays show (output, each display shows a hex digit: display 0 the
+    /// lowest four bits, display 3 the highest four)
+    pub display_value: i16,
+}
+
+impl Default for BoardPorts {
+    fn default() -> Self {
+        Self { green_leds: 5, red_leds: 6, keys: 7, switches: 8, display_control: 9, display_value: 10 }
+    }
+}
+
+impl BoardPorts {
+    /// Smallest range of ports that contains all of them, to attach the [Board] to
+    pub fn range(&self) -> std::ops::RangeInclusive<i16> {
+        let ports = [self.green_leds, self.red_leds, self.keys, self.switches, self.display_control, self.display_value];
+        *ports.iter().min().unwrap()..=*ports.iter().max().unwrap()
+    }
+}
+
+/// Number of LEDs of each color, switches and pushbuttons that are drawn
+const LEDS: usize = 8;
+const SWITCHES: usize = 8;
+const KEYS: usize = 4;
+const DISPLAYS: usize = 4;
+
+/// The peripherals of the DE1/DE2 FPGA board the course runs SISA on: LEDs, 7-segment displays,
+/// switches and pushbuttons. The switches and pushbuttons are inputs whose state is scripted with an
+/// [IoTimeline] on their ports. Every time the program writes to one of the outputs, the state of
+/// the board is drawn on the terminal.
+pub struct Board {
+    ports: BoardPorts,
+    inputs: IoTimeline,
+    green_leds: i16,
+    red_leds: i16,
+    display_control: i16,
+    display_value: i16,
+}
+
+impl Board {
+    /// Create a board, with the switches and pushbuttons following the `inputs` timeline
+    pub fn new(ports: BoardPorts, inputs: IoTimeline) -> Self {
+        Self { ports, inputs, green_leds: 0, red_leds: 0, display_control: 0, display_value: 0 }
+    }
+
+    fn input(&mut self, port: i16) -> i16 {
+        self.inputs.read(&MemAddr(port)).ok().flatten().map(|v| v.0).unwrap_or(0)
+    }
+}
+
+fn bits(value: i16, n: usize, on: &str, off: &str) -> String {
+    (0..n).rev().map(|i| if value & (1 << i) != 0 { on } else { off }).collect()
+}
+
+/// Draw a hex digit as three lines of a 7-segment display
+fn seven_segments(digit: u8) -> [String; 3] {
+    // Segments a-g, as usual: a on top, then clockwise, g in the middle
+    const SEGMENTS: [u8; 16] = [
+        0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F, 0x77, 0x7C, 0x39, 0x5E, 0x79, 0x71,
+    ];
+    let s = SEGMENTS[(digit & 0xF) as usize];
+    let seg = |bit: u8, c: char| if s & (1 << bit) != 0 { c } else { ' ' };
+    [
+        format!(" {} ", seg(0, '_')),
+        format!("{}{}{}", seg(5, '|'), seg(6, '_'), seg(1, '|')),
+        format!("{}{}{}", seg(4, '|'), seg(3, '_'), seg(2, '|')),
+    ]
+}
+
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut lines = [String::new(), String::new(), String::new()];
+        for display in (0..DISPLAYS).rev() {
+            let digit = if self.display_control & (1 << display) != 0 {
+                seven_segments((self.display_value >> (4 * display)) as u8)
+            } else { [" ".repeat(3), " ".repeat(3), " ".repeat(3)] };
+            for (line, part) in lines.iter_mut().zip(digit) { line.push_str(&format!(" {part}")); }
+        }
+        writeln!(f, "[BOARD]:")?;
+        writeln!(f, "  LEDR: \x1b[31m{}\x1b[0m  LEDG: \x1b[32m{}\x1b[0m",
+            bits(self.red_leds, LEDS, "●", "○"), bits(self.green_leds, LEDS, "●", "○"))?;
+        for line in lines { writeln!(f, "  {line}")?; }
+        Ok(())
+    }
+}
+
+impl IoDevice for Board {
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> {
+        Ok(if port.0 == self.ports.keys || port.0 == self.ports.switches {
+            Some(Value16Bit(self.input(port.0)))
+        } else { None })
+    }
+
+    fn write(&mut self, port: &MemAddr, value: Value16Bit) {
+        let output = if port.0 == self.ports.green_leds { &mut self.green_leds }
+            else if port.0 == self.ports.red_leds { &mut self.red_leds }
+            else if port.0 == self.ports.display_control { &mut self.display_control }
+            else if port.0 == self.ports.display_value { &mut self.display_value }
+            else { return };
+        *output = value.0;
+        if is_quiet() { return; }
+
+        let keys = self.inputs.peek(&MemAddr(self.ports.keys)).map(|v| v.0).unwrap_or(0);
+        let switches = self.inputs.peek(&MemAddr(self.ports.switches)).map(|v| v.0).unwrap_or(0);
+        print!("{self}");
+        println!("  KEY: {}  SW: {}", bits(keys, KEYS, "▣", "□"), bits(switches, SWITCHES, "▲", "▼"));
+    }
+
+    fn tick(&mut self, clock: &Clock) { self.inputs.tick(clock); }
+
+    // The outputs don't affect what the program will read, only the inputs do
+    fn hash_state(&self, hasher: &mut dyn Hasher) { self.inputs.hash_state(hasher); }
+
+    fn scheduled_changes(&self) -> Vec<(MemAddr, Trigger)> { self.inputs.scheduled_changes() }
+
+    fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> {
+        let outputs = [
+            (self.ports.green_leds, self.green_leds),
+            (self.ports.red_leds, self.red_leds),
+            (self.ports.display_control, self.display_control),
+            (self.ports.display_value, self.display_value),
+        ];
+        let mut values = self.inputs.port_values();
+        values.extend(outputs.into_iter().map(|(port, value)| (MemAddr(port), Value16Bit(value))));
+        values
+    }
+}
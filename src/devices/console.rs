@@ -0,0 +1,46 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{io::{IoDevice, IoError}, execute::{MemAddr, Value16Bit}};
+
+/// Port the [Console] is on by default
+pub const DEFAULT_CONSOLE_PORT: i16 = 15;
+
+/// A text console: every `OUT` to its port appends the low byte of the value, as an ASCII
+/// character, to the console's buffer. Reading from it gives nothing.
+///
+/// The buffer is shared, so that it can still be looked at once the device has been attached to
+/// the bus (see [Console::buffer]).
+#[derive(Debug)]
+pub struct Console {
+    port: MemAddr,
+    buffer: Rc<RefCell<String>>,
+}
+
+impl Console {
+    /// Create an empty console on the given port
+    pub fn new(port: MemAddr) -> Self {
+        Self { port, buffer: Rc::default() }
+    }
+
+    /// Handle to the text written so far
+    pub fn buffer(&self) -> Rc<RefCell<String>> { Rc::clone(&self.buffer) }
+}
+
+impl IoDevice for Console {
+    fn read(&mut self, _port: &MemAddr) -> Result<Option<Value16Bit>, IoError> { Ok(None) }
+
+    fn write(&mut self, port: &MemAddr, value: Value16Bit) {
+        if *port == self.port {
+            self.buffer.borrow_mut().push((value.0 & 0xFF) as u8 as char);
+        }
+    }
+}
+
+#[test]
+fn test_console() {
+    let mut console = Console::new(MemAddr(3));
+    let buffer = console.buffer();
+    for c in "hi\n".bytes() { console.write(&MemAddr(3), Value16Bit(0x4100 | c as i16)); }
+    console.write(&MemAddr(4), Value16Bit(0x41));
+    assert_eq!(*buffer.borrow(), "hi\n");
+}
@@ -0,0 +1,201 @@
+use std::{cell::RefCell, fmt, io, ops::RangeInclusive, path::{Path, PathBuf}, rc::Rc, str::FromStr};
+
+use crate::execute::{MemAddr, MemoryHook};
+
+/// Address where the [Framebuffer] starts by default (it takes up to `0xBFFF`)
+pub const DEFAULT_FRAMEBUFFER_ADDR: u16 = 0xA000;
+
+/// Size of the screen in text mode: one byte (an ASCII character) per cell
+pub const TEXT_SIZE: (usize, usize) = (80, 25);
+/// Size of the screen in pixel mode: one byte per pixel, in RGB332 (`RRRGGGBB`)
+pub const PIXEL_SIZE: (usize, usize) = (128, 64);
+
+/// What the bytes of a [Framebuffer] mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum FramebufferMode {
+    Text,
+    Pixels,
+}
+
+impl FramebufferMode {
+    /// Width and height of the screen, in characters or pixels
+    pub fn size(&self) -> (usize, usize) {
+        match self {
+            FramebufferMode::Text => TEXT_SIZE,
+            FramebufferMode::Pixels => PIXEL_SIZE,
+        }
+    }
+}
+
+impl FromStr for FramebufferMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text"   => Ok(FramebufferMode::Text),
+            "pixels" => Ok(FramebufferMode::Pixels),
+            other    => Err(format!("unknown framebuffer mode '{other}' (expected text or pixels)")),
+        }
+    }
+}
+
+impl fmt::Display for FramebufferMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            FramebufferMode::Text => "text",
+            FramebufferMode::Pixels => "pixels",
+        })
+    }
+}
+
+/// A VGA-style screen mapped into the memory: the screen is stored row by row starting at its base
+/// address, and every `ST`/`STB` there updates it.
+///
+/// Clones share the same screen, so one can be [hooked](crate::Memory::add_hook) to the memory
+/// while another one is kept around to draw it.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    base: MemAddr,
+    mode: FramebufferMode,
+    screen: Rc<RefCell<Vec<u8>>>,
+}
+
+/// Characters used to draw pixels as ASCII art, from darkest to brightest
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+impl Framebuffer {
+    /// Create a blank framebuffer starting at `base`
+    pub fn new(base: MemAddr, mode: FramebufferMode) -> Self {
+        let (width, height) = mode.size();
+        Self { base, mode, screen: Rc::new(RefCell::new(vec![0; width * height])) }
+    }
+
+    /// The addresses the screen takes up
+    pub fn region(&self) -> RangeInclusive<u16> {
+        let (width, height) = self.mode.size();
+        let start = self.base.0 as u16;
+        start..=start.saturating_add((width * height - 1) as u16)
+    }
+
+    /// Red, green and blue (0 to 255) of an RGB332 pixel
+    fn rgb(pixel: u8) -> [u8; 3] {
+        let scale = |v: u8, max: u16| (v as u16 * 255 / max) as u8;
+        [scale(pixel >> 5, 7), scale((pixel >> 2) & 0b111, 7), scale(pixel & 0b11, 3)]
+    }
+
+    /// Draw the screen as text: in text mode, each byte is its character (or a space, if it can't
+    /// be printed). In pixel mode, each pixel is a character as bright as it is.
+    pub fn to_ascii(&self) -> String {
+        let (width, _) = self.mode.size();
+        let screen = self.screen.borrow();
+        let mut out = String::new();
+        for row in screen.chunks(width) {
+            out.extend(row.iter().map(|&b| match self.mode {
+                FramebufferMode::Text if b.is_ascii_graphic() => b as char,
+                FramebufferMode::Text => ' ',
+                FramebufferMode::Pixels => {
+                    let [r, g, b] = Self::rgb(b);
+                    let brightness = (r as usize + g as usize + b as usize) / 3;
+                    SHADES[brightness * (SHADES.len() - 1) / 255] as char
+                },
+            }));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Draw the screen as a binary PPM image (`P6`). Text mode is drawn as in [to_ascii]
+    /// (Framebuffer::to_ascii) instead, as there's no font to draw it with.
+    pub fn to_ppm(&self) -> Vec<u8> {
+        if self.mode == FramebufferMode::Text { return self.to_ascii().into_bytes(); }
+        let (width, height) = self.mode.size();
+        let mut out = format!("P6\n{width} {height}\n255\n").into_bytes();
+        out.extend(self.screen.borrow().iter().flat_map(|&p| Self::rgb(p)));
+        out
+    }
+}
+
+/// Writes the frames of a [Framebuffer] to numbered files as the program runs: `screen.ppm` becomes
+/// `screen-0001.ppm`, `screen-0002.ppm` and so on. Frames where nothing changed since the last one
+/// written are skipped.
+pub struct FrameDumper {
+    framebuffer: Framebuffer,
+    path: PathBuf,
+    last: Option<Vec<u8>>,
+    frames: usize,
+}
+
+impl FrameDumper {
+    /// Dump the frames of `framebuffer` next to `path`, numbering them
+    pub fn new(framebuffer: Framebuffer, path: impl AsRef<Path>) -> Self {
+        Self { framebuffer, path: path.as_ref().into(), last: None, frames: 0 }
+    }
+
+    /// Write the current frame, if it changed. Returns the file it was written to
+    pub fn dump(&mut self) -> io::Result<Option<PathBuf>> {
+        let screen = self.framebuffer.screen.borrow().clone();
+        if self.last.as_ref() == Some(&screen) { return Ok(None); }
+        self.frames += 1;
+        let path = self.frame_path(self.frames);
+        std::fs::write(&path, self.framebuffer.to_ppm())?;
+        self.last = Some(screen);
+        Ok(Some(path))
+    }
+
+    /// Number of frames written so far
+    pub fn frames(&self) -> usize { self.frames }
+
+    /// File the `n`th frame is written to
+    fn frame_path(&self, n: usize) -> PathBuf {
+        let stem = self.path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let name = match self.path.extension() {
+            Some(extension) => format!("{stem}-{n:04}.{}", extension.to_string_lossy()),
+            None => format!("{stem}-{n:04}"),
+        };
+        self.path.with_file_name(name)
+    }
+}
+
+impl MemoryHook for Framebuffer {
+    fn write(&mut self, addr: &MemAddr, value: i8) {
+        let offset = addr.0.wrapping_sub(self.base.0) as u16 as usize;
+        if let Some(b) = self.screen.borrow_mut().get_mut(offset) {
+            *b = value as u8;
+        }
+    }
+}
+
+#[test]
+fn test_framebuffer() {
+    use crate::Memory;
+    let fb = Framebuffer::new(MemAddr(DEFAULT_FRAMEBUFFER_ADDR as i16), FramebufferMode::Text);
+    let mut memory = Memory::new();
+    memory.add_hook(fb.region(), Box::new(fb.clone()));
+    memory.insert_word(&MemAddr(DEFAULT_FRAMEBUFFER_ADDR as i16), i16::from_le_bytes(*b"Hi"));
+    memory.insert_byte(&MemAddr((DEFAULT_FRAMEBUFFER_ADDR + 80) as i16), b'!' as i8);
+    let ascii = fb.to_ascii();
+    let mut lines = ascii.lines();
+    assert_eq!(lines.next().unwrap().trim_end(), "Hi");
+    assert_eq!(lines.next().unwrap().trim_end(), "!");
+    assert_eq!(memory.get_word(&MemAddr(DEFAULT_FRAMEBUFFER_ADDR as i16)), Some(i16::from_le_bytes(*b"Hi")));
+}
+
+#[test]
+fn test_frame_dumper() {
+    use crate::Memory;
+    let fb = Framebuffer::new(MemAddr(DEFAULT_FRAMEBUFFER_ADDR as i16), FramebufferMode::Pixels);
+    let mut memory = Memory::new();
+    memory.add_hook(fb.region(), Box::new(fb.clone()));
+    let dir = std::env::temp_dir();
+    let mut dumper = FrameDumper::new(fb.clone(), dir.join(format!("sicmulador-frames-{}.ppm", std::process::id())));
+
+    let first = dumper.dump().unwrap().unwrap();
+    assert!(first.to_string_lossy().ends_with("-0001.ppm"));
+    assert_eq!(dumper.dump().unwrap(), None);
+    memory.insert_byte(&MemAddr(DEFAULT_FRAMEBUFFER_ADDR as i16), -1);
+    let second = dumper.dump().unwrap().unwrap();
+    assert!(second.to_string_lossy().ends_with("-0002.ppm"));
+    assert_ne!(std::fs::read(&first).unwrap(), std::fs::read(&second).unwrap());
+    assert_eq!(dumper.frames(), 2);
+    [first, second].iter().for_each(|f| std::fs::remove_file(f).unwrap());
+}
@@ -0,0 +1,117 @@
+use std::{collections::VecDeque, hash::{Hash, Hasher}, io::{self, BufRead, IsTerminal, Write}};
+
+use crate::{io::{IoDevice, IoError}, execute::{MemAddr, Value16Bit}, parse_value};
+
+/// Default port of `KEY-STATUS`
+pub const DEFAULT_KEY_STATUS_PORT: i16 = 1;
+/// Default port of `KEY-DATA`
+pub const DEFAULT_KEY_DATA_PORT: i16 = 0;
+
+/// Where the keys pressed on a [Keyboard] come from
+#[derive(Debug)]
+pub enum KeySource {
+    /// A list of keys given beforehand
+    Queue(VecDeque<Value16Bit>),
+    /// The terminal: when the program checks for a key and there is none, a line is read from
+    /// stdin. Once stdin runs out, checking for a key stops the simulation
+    Terminal,
+}
+
+/// A keyboard following the `KEY-STATUS`/`KEY-DATA` handshake:
+/// - Reading `KEY-STATUS` gives 1 while there is a key available, and 0 otherwise
+/// - Reading `KEY-DATA` gives the available key (0 if there is none). It can be read many times
+/// - Writing anything to `KEY-STATUS` acknowledges the key, so the status goes back to 0 until the
+///   next key is available
+pub struct Keyboard {
+    status_port: MemAddr,
+    data_port: MemAddr,
+    source: KeySource,
+    current: Option<Value16Bit>,
+    /// Times the terminal has been asked for a key
+    reads: usize,
+}
+
+impl Keyboard {
+    /// Create a keyboard on the given ports
+    pub fn new(status_port: MemAddr, data_port: MemAddr, source: KeySource) -> Self {
+        Self { status_port, data_port, source, current: None, reads: 0 }
+    }
+
+    fn next_key(&mut self) -> Result<Option<Value16Bit>, IoError> {
+        match &mut self.source {
+            KeySource::Queue(keys) => Ok(keys.pop_front()),
+            KeySource::Terminal => {
+                self.reads += 1;
+                if io::stdin().is_terminal() {
+                    print!("[KEYBOARD]: press a key (decimal, hex or 'c'): ");
+                    let _ = io::stdout().flush();
+                }
+                let error = |reason: String| IoError::NoInput { port: self.status_port.clone(), reason };
+                let mut line = String::new();
+                match io::stdin().lock().read_line(&mut line) {
+                    Ok(0) => return Err(error("the input has ended".into())),
+                    Ok(_) => {},
+                    Err(e) => return Err(error(e.to_string())),
+                }
+                match parse_value(&line) {
+                    Some(v) => Ok(Some(Value16Bit(v))),
+                    None => {
+                        println!("[KEYBOARD]: could not understand '{}', no key was pressed", line.trim());
+                        Ok(None)
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl IoDevice for Keyboard {
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> {
+        Ok(if *port == self.status_port {
+            if self.current.is_none() { self.current = self.next_key()?; }
+            Some(Value16Bit(self.current.is_some() as i16))
+        } else if *port == self.data_port {
+            Some(self.current.clone().unwrap_or(Value16Bit(0)))
+        } else { None })
+    }
+
+    fn write(&mut self, port: &MemAddr, _value: Value16Bit) {
+        if *port == self.status_port { self.current = None; }
+    }
+
+    fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> {
+        vec![
+            (self.status_port.clone(), Value16Bit(self.current.is_some() as i16)),
+            (self.data_port.clone(), self.current.clone().unwrap_or(Value16Bit(0))),
+        ]
+    }
+
+    fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        self.current.as_ref().map(|v| v.0).hash(&mut hasher);
+        match &self.source {
+            KeySource::Queue(keys) => keys.iter().for_each(|k| k.0.hash(&mut hasher)),
+            // Every time it's asked, the terminal may give something different
+            KeySource::Terminal => self.reads.hash(&mut hasher),
+        }
+    }
+}
+
+#[test]
+fn test_keyboard_handshake() {
+    use crate::{read_instructions, Processador, Registers, Memory, ProgCounter, Halt};
+    use std::collections::HashMap;
+
+    let keys = VecDeque::from([Value16Bit('a' as i16), Value16Bit('b' as i16)]);
+    let keyboard = Keyboard::new(MemAddr(DEFAULT_KEY_STATUS_PORT), MemAddr(DEFAULT_KEY_DATA_PORT), KeySource::Queue(keys));
+    // Wait for a key, read it twice and acknowledge it, twice
+    let program = "IN R1, 1\nBZ R1, -2\nIN R2, 0\nIN R2, 0\nOUT 1, R0\n\
+        IN R1, 1\nBZ R1, -2\nIN R3, 0\nOUT 1, R0\nIN R4, 1\nIN R5, 0";
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+    cpu.attach_device(0..=1, Box::new(keyboard));
+    crate::set_quiet(true);
+    let halt = (0..100).find_map(|_| cpu.execute_next(false).err());
+    assert!(matches!(halt, Some(Halt::NoInstruction { .. })));
+    assert_eq!((cpu.register(2), cpu.register(3)), ('a' as i16, 'b' as i16));
+    // Once they run out, there is no key available
+    assert_eq!((cpu.register(4), cpu.register(5)), (0, 0));
+}
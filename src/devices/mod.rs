@@ -0,0 +1,15 @@
+mod timeline;
+mod keyboard;
+mod prompt;
+mod board;
+mod console;
+mod framebuffer;
+mod rng;
+
+pub use timeline::{IoTimeline, Trigger};
+pub use keyboard::{Keyboard, KeySource, DEFAULT_KEY_STATUS_PORT, DEFAULT_KEY_DATA_PORT};
+pub use prompt::StdinPrompt;
+pub use board::{Board, BoardPorts};
+pub use console::{Console, DEFAULT_CONSOLE_PORT};
+pub use framebuffer::{Framebuffer, FrameDumper, FramebufferMode, DEFAULT_FRAMEBUFFER_ADDR, TEXT_SIZE, PIXEL_SIZE};
+pub use rng::{Rng, DEFAULT_RNG_PORT};
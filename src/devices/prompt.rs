@@ -0,0 +1,86 @@
+use std::{hash::{Hash, Hasher}, io::{self, BufRead, IsTerminal, Write}};
+
+use crate::{io::{IoDevice, IoError}, execute::{MemAddr, Value16Bit}, parse_value};
+
+/// Asks for the value of any port that is read. On a terminal, the user is prompted for it;
+/// otherwise a line is read from stdin for every read. The value may be given in decimal, hex or as
+/// a character between single quotes (`'a'`). Running out of lines, or a line that isn't a value
+/// when not on a terminal, stops the simulation.
+///
+/// Meant to be used as the [fallback](crate::IOSystem::set_fallback) of the bus, so that it's only
+/// asked for the ports that no other device handles.
+#[derive(Default)]
+pub struct StdinPrompt {
+    reads: usize,
+    /// Where the lines come from instead of stdin, if anywhere
+    input: Option<Box<dyn BufRead>>,
+}
+
+impl StdinPrompt {
+    /// Create a new prompt
+    pub fn new() -> Self { Self::default() }
+
+    /// Read the values from `input` instead of stdin, one per line
+    pub fn from_reader(input: impl BufRead + 'static) -> Self {
+        Self { reads: 0, input: Some(Box::new(input)) }
+    }
+}
+
+impl IoDevice for StdinPrompt {
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> {
+        let interactive = self.input.is_none() && io::stdin().is_terminal();
+        let error = |reason: String| IoError::NoInput { port: port.clone(), reason };
+        loop {
+            if interactive {
+                print!("[INPUT]: value for port {port} (decimal, hex or 'c'): ");
+                let _ = io::stdout().flush();
+            }
+            let mut line = String::new();
+            let read = match &mut self.input {
+                Some(input) => input.read_line(&mut line),
+                None => io::stdin().lock().read_line(&mut line),
+            };
+            match read {
+                Ok(0) => return Err(error("the input has ended".into())),
+                Ok(_) => {},
+                Err(e) => return Err(error(e.to_string())),
+            }
+            match parse_value(&line) {
+                Some(v) => {
+                    self.reads += 1;
+                    return Ok(Some(Value16Bit(v)));
+                },
+                None if interactive => println!("[INPUT]: could not understand '{}', try again", line.trim()),
+                None => return Err(error(format!("could not understand '{}'", line.trim()))),
+            }
+        }
+    }
+
+    fn write(&mut self, _port: &MemAddr, _value: Value16Bit) {}
+
+    // Every read may give something different, so the state never repeats after one
+    fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        self.reads.hash(&mut hasher);
+    }
+}
+
+#[test]
+fn test_prompt() {
+    let mut prompt = StdinPrompt::from_reader(io::Cursor::new("12\n0x0F\n'a'\n"));
+    for expected in [12, 15, 'a' as i16] {
+        assert_eq!(prompt.read(&MemAddr(1)).unwrap().unwrap().0, expected);
+    }
+    assert!(matches!(prompt.read(&MemAddr(1)), Err(IoError::NoInput { .. })));
+    let mut prompt = StdinPrompt::from_reader(io::Cursor::new("twelve\n"));
+    assert!(matches!(prompt.read(&MemAddr(1)), Err(IoError::NoInput { .. })));
+
+    // The simulation stops, instead of going on (or panicking) without a value
+    use crate::{read_instructions, Processador, Registers, Memory, ProgCounter, Halt};
+    for input in ["", "twelve\n"] {
+        let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions("IN R1, 5").unwrap(), Default::default());
+        cpu.set_io_fallback(Some(Box::new(StdinPrompt::from_reader(io::Cursor::new(input)))));
+        crate::set_quiet(true);
+        assert!(matches!(cpu.execute_next(false), Err(Halt::Io { error: IoError::NoInput { .. }, pc: ProgCounter(0) })));
+        assert_eq!(cpu.pc().0, 0);
+    }
+}
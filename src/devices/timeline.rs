@@ -0,0 +1,174 @@
+use std::{collections::{HashMap, VecDeque}, hash::{Hash, Hasher}};
+
+use crate::{io::{Clock, IoDevice, IoError}, execute::{MemAddr, Value16Bit}};
+
+/// What makes a change scheduled in an [IoTimeline] happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    /// Once this many instructions have been executed
+    Step(usize),
+    /// Once this many cycles have elapsed
+    Cycle(usize),
+    /// Once the port has been read this many times
+    Reads(usize),
+}
+
+impl Trigger {
+    fn is_due(&self, clock: &Clock, reads: usize) -> bool {
+        match self {
+            Trigger::Step(step)   => clock.step >= *step,
+            Trigger::Cycle(cycle) => clock.cycle >= *cycle,
+            Trigger::Reads(n)     => reads >= *n,
+        }
+    }
+
+    fn key(&self) -> usize {
+        match self { Trigger::Step(n) | Trigger::Cycle(n) | Trigger::Reads(n) => *n }
+    }
+
+    /// Whether this trigger became due after `other`, which is only known for those of the same
+    /// kind
+    fn is_after(&self, other: &Trigger) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other) && self.key() > other.key()
+    }
+}
+
+#[derive(Debug, Default)]
+struct PortTimeline {
+    value: Option<Value16Bit>,
+    pending: Vec<(Trigger, Value16Bit)>,
+    queue: VecDeque<Value16Bit>,
+    reads: usize,
+}
+
+/// Input ports whose values change over time, following a script. Each port has:
+/// - A current value, which may be [set](IoTimeline::set) from the start
+/// - Changes to that value, [scheduled](IoTimeline::schedule) to happen at a given step or cycle,
+///   or after the port has been read a number of times
+/// - A queue of values, [enqueued](IoTimeline::enqueue) in order, each of which is consumed by a
+///   single read. While there are values in the queue, they take preference over the current value
+///
+/// When several changes of a port are due at once, the last one to happen wins. Changes of different
+/// kinds that become due at the same time (like a step and a number of reads) are applied in the
+/// order they were scheduled.
+///
+/// Writing to the ports does nothing.
+#[derive(Debug, Default)]
+pub struct IoTimeline {
+    ports: HashMap<MemAddr, PortTimeline>,
+    clock: Clock,
+}
+
+impl IoTimeline {
+    /// Create an empty timeline
+    pub fn new() -> Self { Self::default() }
+
+    /// Set the current value of a port
+    pub fn set(&mut self, port: MemAddr, value: Value16Bit) {
+        self.ports.entry(port).or_default().value = Some(value);
+    }
+
+    /// Make the port take `value` once `trigger` happens
+    pub fn schedule(&mut self, port: MemAddr, trigger: Trigger, value: Value16Bit) {
+        self.ports.entry(port).or_default().pending.push((trigger, value));
+    }
+
+    /// Add a value to the port's queue, which will be returned by a single read
+    pub fn enqueue(&mut self, port: MemAddr, value: Value16Bit) {
+        self.ports.entry(port).or_default().queue.push_back(value);
+    }
+
+    /// What the next read of the port would give, without actually reading it
+    pub fn peek(&self, port: &MemAddr) -> Option<Value16Bit> {
+        let p = self.ports.get(port)?;
+        p.queue.front().or(p.value.as_ref()).cloned()
+    }
+
+    fn apply_due(clock: &Clock, port: &mut PortTimeline) {
+        let reads = port.reads;
+        let (due, pending): (Vec<_>, Vec<_>) = port.pending.drain(..).partition(|(t, _)| t.is_due(clock, reads));
+        port.pending = pending;
+        // Everything due now became due since the last time, so only triggers of the same kind can
+        // tell which one happened last. Otherwise, the one scheduled last wins
+        let last = due.iter().rev().find(|(t, _)| !due.iter().any(|(other, _)| other.is_after(t)));
+        if let Some((_, value)) = last { port.value = Some(value.clone()); }
+    }
+}
+
+impl IoDevice for IoTimeline {
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> {
+        let Some(p) = self.ports.get_mut(port) else { return Ok(None) };
+        let value = p.queue.pop_front().or(p.value.clone());
+        p.reads += 1;
+        Self::apply_due(&self.clock, p);
+        Ok(value)
+    }
+
+    fn write(&mut self, _port: &MemAddr, _value: Value16Bit) {}
+
+    fn tick(&mut self, clock: &Clock) {
+        self.clock = *clock;
+        self.ports.values_mut().for_each(|p| Self::apply_due(clock, p));
+    }
+
+    fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        let mut ports: Vec<_> = self.ports.iter().collect();
+        ports.sort_by_key(|(k, _)| k.0);
+        for (port, p) in ports {
+            port.0.hash(&mut hasher);
+            p.value.as_ref().map(|v| v.0).hash(&mut hasher);
+            p.queue.iter().for_each(|v| v.0.hash(&mut hasher));
+            // The time left until the changes is left to scheduled_changes, but the reads left
+            // are part of the state. Once they're done the same state may repeat again
+            for (trigger, value) in &p.pending {
+                (trigger, value.0).hash(&mut hasher);
+                if let Trigger::Reads(_) = trigger { p.reads.hash(&mut hasher); }
+            }
+        }
+    }
+
+    fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> {
+        self.ports.keys().filter_map(|port| Some((port.clone(), self.peek(port)?))).collect()
+    }
+
+    fn scheduled_changes(&self) -> Vec<(MemAddr, Trigger)> {
+        self.ports.iter()
+            .flat_map(|(port, p)| p.pending.iter().map(move |(trigger, _)| (port.clone(), *trigger)))
+            .filter(|(_, trigger)| !matches!(trigger, Trigger::Reads(_)))
+            .collect()
+    }
+}
+
+#[test]
+fn test_timeline() {
+    let mut timeline = IoTimeline::new();
+    let port = MemAddr(1);
+    timeline.set(port.clone(), Value16Bit(0));
+    timeline.schedule(port.clone(), Trigger::Step(10), Value16Bit(1));
+    timeline.schedule(port.clone(), Trigger::Reads(3), Value16Bit(2));
+    timeline.enqueue(MemAddr(0), Value16Bit(5));
+    timeline.enqueue(MemAddr(0), Value16Bit(7));
+
+    let read = |t: &mut IoTimeline, p: &MemAddr| t.read(p).unwrap().map(|v| v.0);
+    assert_eq!(read(&mut timeline, &MemAddr(0)), Some(5));
+    assert_eq!(read(&mut timeline, &MemAddr(0)), Some(7));
+    assert_eq!(read(&mut timeline, &MemAddr(0)), None);
+
+    assert_eq!(read(&mut timeline, &port), Some(0));
+    timeline.tick(&Clock { step: 10, cycle: 30 });
+    assert_eq!(read(&mut timeline, &port), Some(1));
+    assert_eq!(read(&mut timeline, &port), Some(1));
+    assert_eq!(read(&mut timeline, &port), Some(2));
+
+    // The last step due is 25, but it can't be compared with the cycle, which was scheduled later
+    timeline.schedule(port.clone(), Trigger::Step(25), Value16Bit(3));
+    timeline.schedule(port.clone(), Trigger::Step(20), Value16Bit(4));
+    timeline.schedule(port.clone(), Trigger::Cycle(40), Value16Bit(5));
+    timeline.schedule(port.clone(), Trigger::Step(21), Value16Bit(6));
+    timeline.tick(&Clock { step: 30, cycle: 90 });
+    assert_eq!(read(&mut timeline, &port), Some(5));
+    timeline.schedule(port.clone(), Trigger::Step(32), Value16Bit(7));
+    timeline.schedule(port.clone(), Trigger::Step(31), Value16Bit(8));
+    timeline.tick(&Clock { step: 32, cycle: 96 });
+    assert_eq!(read(&mut timeline, &port), Some(7));
+}
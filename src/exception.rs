@@ -0,0 +1,71 @@
+use std::fmt;
+
+use crate::execute::MemAddr;
+
+/// The kind of memory access that was being performed when an exception was raised. Needed to
+/// tell instruction exceptions (fetch) apart from data exceptions (LD/ST)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Access {
+    Fetch,
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Access::Fetch => "fetching",
+            Access::Read  => "reading",
+            Access::Write => "writing",
+        })
+    }
+}
+
+/// Every exception the [Processador](crate::Processador) can raise while executing. The numeric
+/// code of each one (see [code](Exception::code)) is the one SISA uses to identify it.
+#[derive(Debug, Clone, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Exception {
+    #[error("TLB miss while {access} address {addr}")]
+    TlbMiss { access: Access, addr: MemAddr },
+    #[error("page not present while {access} address {addr}")]
+    InvalidPage { access: Access, addr: MemAddr },
+    #[error("protected page while {access} address {addr}")]
+    ProtectedPage { access: Access, addr: MemAddr },
+    #[error("instruction {verb} is not part of the ISA profile (or uses a register it doesn't have)")]
+    IllegalInstruction { verb: String },
+    #[error("tried to run the privileged instruction {verb} in user mode")]
+    ProtectedInstruction { verb: String },
+    #[error("system call with argument 0x{arg:0>4X}")]
+    SystemCall { arg: i16 },
+}
+
+impl Exception {
+    /// SISA's exception identifier. For the memory related ones, instruction exceptions have even
+    /// codes and their data counterparts are the next odd one
+    pub fn code(&self) -> u16 {
+        let (base, access) = match self {
+            Exception::TlbMiss { access, .. }       => (6, access),
+            Exception::InvalidPage { access, .. }   => (8, access),
+            Exception::ProtectedPage { access, .. } => (10, access),
+            Exception::IllegalInstruction { .. }    => return 0,
+            Exception::ProtectedInstruction { .. }  => return 13,
+            Exception::SystemCall { .. }            => return 14,
+        };
+        if *access == Access::Fetch { base } else { base + 1 }
+    }
+
+    /// The extra information the handler gets in `S3`: the address that caused the exception, or
+    /// the argument of `CALLS`
+    pub fn argument(&self) -> i16 {
+        match self {
+            Exception::TlbMiss { addr, .. }
+            | Exception::InvalidPage { addr, .. }
+            | Exception::ProtectedPage { addr, .. } => addr.0,
+            Exception::IllegalInstruction { .. }
+            | Exception::ProtectedInstruction { .. } => 0,
+            Exception::SystemCall { arg }           => *arg,
+        }
+    }
+}
@@ -1,14 +1,30 @@
 use::std::{
     collections::HashMap,
     fmt,
-    ops::{Index, IndexMut}, mem::transmute,
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::{Index, IndexMut, RangeInclusive}, mem::transmute,
 };
 
-use crate::{print_info, norm_n, Instructions};
+use crate::{print_info, is_quiet, norm_n, Instructions};
+use crate::exception::{Access, Exception};
 use crate::parsing::ParseError;
 use crate::spec::Instruction;
+use crate::tlb::Mmu;
+use crate::profile::IsaProfile;
+use crate::loop_detection::{LoopDetector, LoopPoint};
+use crate::devices::Trigger;
+use crate::io::{Clock, IOSystem, IoDevice, IoError, MmioRegion};
+use crate::output::OutputRecord;
+use crate::journal::{Journal, JournalEntry};
 
 const INSTRUCTS_SLOW: [&str; 4] = ["LD", "LDB", "ST", "STB"];
+const INSTRUCTS_PRIVILEGED: [&str; 10] = ["RDS", "WRS", "EI", "DI", "RETI", "WRPI", "WRVI", "WRPD", "WRVD", "FLUSH"];
+const INSTRUCTS_IO: [&str; 2] = ["IN", "OUT"];
+
+/// Bit of the PSW (`S7`) that is set while in system mode
+pub const PSW_SYSTEM_MODE: i16 = 1 << 0;
+/// Bit of the PSW (`S7`) that is set while interrupts are enabled
+pub const PSW_INTERRUPTS_ENABLED: i16 = 1 << 1;
 
 impl Processador {
     /// Maximum allowed number of instructions to be run, to avoid generating infinite output wrt
@@ -28,14 +44,28 @@ impl Processador {
             memory:init_mem,
             pc: init_pc,
             instr_memory: instructions,
-            io: IOSystem(init_io),
+            io: IOSystem::from_ports(init_io),
             instrs_fetes: NumInstruccions::default(),
+            mmu: None,
+            sys_regs: Registers::system(),
+            exception_handler: None,
+            syscall_handler: None,
+            protect_io: false,
+            profile: IsaProfile::default(),
+            loop_detector: Some(LoopDetector::default()),
+            output_log: Vec::new(),
+            mmio: Vec::new(),
+            effects: Effects::default(),
+            journal: None,
+            last_reads: HashMap::new(),
         }
     }
     #[rustfmt::skip]
-    /// Execute any valid instruction directly, without going through the Program Counter
-    pub fn execute_raw(&mut self, inst: &Instruction) {
-        println!("[INFO]: Running \x1b[1;4;32m{:?}\x1b[0m", inst);
+    /// Execute any valid instruction directly, without going through the Program Counter. If the
+    /// instruction can't be completed (see [Fault]), the state is left as it was before executing it
+    pub fn execute_raw(&mut self, inst: &Instruction) -> Result<(), Fault> {
+        if !is_quiet() { println!("[INFO]: Running \x1b[1;4;32m{:?}\x1b[0m", inst); }
+        let clock = self.instrs_fetes.clock();
 
         if INSTRUCTS_SLOW.contains(&&*inst.get_verb()) {
             print_info("This instruction is SLOW (memory)"); 
@@ -45,6 +75,16 @@ impl Processador {
             self.instrs_fetes.fast += 1;
         }
 
+        self.effects = Effects::default();
+        let before = self.regs.0;
+
+        if !self.profile.allows(inst) || self.profile.missing_register(inst).is_some() {
+            return Err(Exception::IllegalInstruction { verb: inst.get_verb() }.into());
+        }
+        if self.mode() == Mode::User && self.is_privileged(inst) {
+            return Err(Exception::ProtectedInstruction { verb: inst.get_verb() }.into());
+        }
+
         match inst {
             Instruction::AND { a, b, d }      => self.regs[d].0 = self.regs[a].0 & self.regs[b].0,
             Instruction::OR { a, b, d }       => self.regs[d].0 = self.regs[a].0 | self.regs[b].0,
@@ -61,79 +101,534 @@ impl Processador {
             // TODO: Remove unnecssary use of transmute (use `as`)
             Instruction::CMPLTU { a, b, d }   => unsafe { self.regs[d].0 = (transmute::<i16, u16>(self.regs[a].0) < transmute(self.regs[b].0)) as i16 },
             Instruction::CMPLEU { a, b, d }   => unsafe { self.regs[d].0 = (transmute::<i16, u16>(self.regs[a].0) <= transmute(self.regs[b].0)) as i16 },
-            Instruction::LD { a, d, offset }  => self.regs[d].0 = self.memory.get_word(&(se_6(offset.0) + self.regs[a].0).into()).unwrap_or_else(|| {
-                print_info(&format!("Tried to access uninitialized memory (WORD) at addr: '{}' (hex 0x{0:X})", se_6(offset.0) + self.regs[a].0));
-                panic!();
-            }), 
-            Instruction::LDB { a, d, offset } => self.regs[d].0 = se_8(self.memory.get_byte(&(se_6(offset.0) + self.regs[a].0).into()).unwrap_or_else(||{
-                print_info(&format!("Tried to access uninitialized memory (BYTE) at addr: '{}' (hex 0x{0:X})", se_6(offset.0) + self.regs[a].0));
-                panic!();
-            })), 
-            Instruction::ST  { a, b, offset } => self.memory.insert_word(&(self.regs[b].0 + se_6(offset.0)).into(), self.regs[a].0),
-            Instruction::STB { a, b, offset } => self.memory.insert_byte(&(self.regs[b].0 + se_6(offset.0)).into(), (self.regs[a].0 & 0xF) as i8),
+            Instruction::LD { a, d, offset }  => {
+                let addr = self.translate(&self.regs[a].0.wrapping_add(se_6(offset.0)).into(), Access::Read)?;
+                self.regs[d].0 = match self.mmio_port(&addr.align()) {
+                    Some((port, _)) => self.read_port(&port)?,
+                    None => self.record_read(&addr, 2).get_word(&addr).ok_or(Fault::Uninitialized(addr))?,
+                }
+            },
+            Instruction::LDB { a, d, offset } => {
+                let addr = self.translate(&self.regs[a].0.wrapping_add(se_6(offset.0)).into(), Access::Read)?;
+                self.regs[d].0 = se_8(match self.mmio_port(&addr) {
+                    Some((port, high)) => {
+                        let word = self.read_port(&port)?;
+                        (if high { word >> 8 } else { word }) as i8
+                    },
+                    None => self.record_read(&addr, 1).get_byte(&addr).ok_or(Fault::Uninitialized(addr))?,
+                })
+            },
+            Instruction::ST  { a, b, offset } => {
+                let addr = self.translate(&self.regs[a].0.wrapping_add(se_6(offset.0)).into(), Access::Write)?;
+                match self.mmio_port(&addr.align()) {
+                    Some((port, _)) => self.write_port(&port, self.regs[b].0)?,
+                    None => {
+                        self.record_write(&addr.align(), self.regs[b].0 as i8);
+                        self.record_write(&MemAddr(addr.align().0 + 1), (self.regs[b].0 >> 8) as i8);
+                        self.memory.insert_word(&addr, self.regs[b].0)
+                    },
+                }
+            },
+            Instruction::STB { a, b, offset } => {
+                let addr = self.translate(&self.regs[a].0.wrapping_add(se_6(offset.0)).into(), Access::Write)?;
+                match self.mmio_port(&addr) {
+                    Some((port, high)) => {
+                        // Only one of the port's bytes is written, so the other one is kept
+                        let word = self.io.peek(&port).map(|v| v.0).unwrap_or(0);
+                        let byte = self.regs[b].0 & 0xFF;
+                        self.write_port(&port, if high { (word & 0x00FF) | (byte << 8) } else { (word & !0xFF) | byte })?
+                    },
+                    None => {
+                        self.record_write(&addr, (self.regs[b].0 & 0xFF) as i8);
+                        self.memory.insert_byte(&addr, (self.regs[b].0 & 0xFF) as i8)
+                    },
+                }
+            },
             Instruction::BZ  { a, offset }    => if self.regs[a].0 == 0 {self.pc.0 = (self.pc.0 as i16 + 2*se_8(offset.0)) as u16 }
             Instruction::BNZ { a, offset }    => if self.regs[a].0 != 0 {self.pc.0 = (self.pc.0 as i16 + 2*se_8(offset.0)) as u16 }
             Instruction::MOVI { d, n }        => self.regs[d].0 = se_8(n.0),
             Instruction::MOVHI { d, n }       => self.regs[d].0 |= (n.0 as i16) << 8,
-            Instruction::IN { d, n }          => self.regs[d].0 = self.io.get(n).expect("Tried to access non existent IO address").0,
-            Instruction::OUT { d, n }         => println!("[OUTPUT]: value '0x{0:0>4X}' ('{}') was printed on addr '{}'", self.regs[n].0, d),
+            Instruction::IN { d, n }          => self.regs[d].0 = self.read_port(n)?,
+            Instruction::OUT { d, n }         => {
+                if !is_quiet() { println!("[OUTPUT]: value '0x{0:0>4X}' ('{}') was printed on addr '{}'", self.regs[n].0, d); }
+                self.write_port(d, self.regs[n].0)?;
+                self.output_log.push(OutputRecord { step: clock.step, cycle: clock.cycle, port: d.clone(), value: Value16Bit(self.regs[n].0) });
+            },
             Instruction::JALR { a, d }        => { self.regs[d].0 = self.pc.0 as i16;   self.pc.0 = self.regs[a].0 as u16; }, // TODO: Test
+            Instruction::WRPI { a, b }        => self.with_mmu(|mmu, regs| mmu.instructions.write_physical(regs[a].0, regs[b].0)),
+            Instruction::WRVI { a, b }        => self.with_mmu(|mmu, regs| mmu.instructions.write_virtual(regs[a].0, regs[b].0)),
+            Instruction::WRPD { a, b }        => self.with_mmu(|mmu, regs| mmu.data.write_physical(regs[a].0, regs[b].0)),
+            Instruction::WRVD { a, b }        => self.with_mmu(|mmu, regs| mmu.data.write_virtual(regs[a].0, regs[b].0)),
+            Instruction::FLUSH                => self.with_mmu(|mmu, _| { mmu.instructions.flush(); mmu.data.flush(); }),
+            Instruction::RDS { d, a }         => self.regs[d] = self.sys_regs[a],
+            Instruction::WRS { d, a }         => self.sys_regs[d] = self.regs[a],
+            Instruction::EI                   => self.sys_regs[&PSW].0 |= PSW_INTERRUPTS_ENABLED,
+            Instruction::DI                   => self.sys_regs[&PSW].0 &= !PSW_INTERRUPTS_ENABLED,
+            Instruction::RETI                 => { self.sys_regs[&PSW] = self.sys_regs[&SAVED_PSW]; self.pc.0 = self.sys_regs[&RETURN_ADDR].0 as u16; },
+            Instruction::CALLS { a }          => return Err(Exception::SystemCall { arg: self.regs[a].0 }.into()),
             Instruction::NOP                  => {},
         }
-        println!();
+        self.effects.register_reads = inst.sources().iter().map(|r| r.0).collect();
+        if let Some(d) = inst.destination() {
+            self.effects.register_writes.push((d.0, before[d.0 as usize].0, self.regs[d].0));
+        }
+        if !is_quiet() { println!(); }
+        Ok(())
+    }
+
+    /// Record that `len` bytes are read from `addr` (aligned if it's a word)
+    fn record_read(&mut self, addr: &MemAddr, len: i16) -> &Memory {
+        let first = if len == 2 { addr.align() } else { addr.clone() };
+        self.effects.memory_reads.extend((0..len).map(|i| MemAddr(first.0 + i)));
+        &self.memory
+    }
+
+    /// Read an IO port, recording it
+    fn read_port(&mut self, port: &MemAddr) -> Result<i16, IoError> {
+        self.check_devices(port)?;
+        let value = self.io.read(port)?.0;
+        self.effects.io_reads.push((port.clone(), value));
+        self.last_reads.insert(port.clone(), self.instrs_fetes.total());
+        Ok(value)
+    }
+
+    /// Write to an IO port, recording it
+    fn write_port(&mut self, port: &MemAddr, value: i16) -> Result<(), IoError> {
+        self.check_devices(port)?;
+        self.io.write(port, Value16Bit(value));
+        self.effects.io_writes.push((port.clone(), value));
+        Ok(())
+    }
+
+    /// The devices can't be used again by instructions that they have already seen (see
+    /// [Journal::devices_ahead])
+    fn check_devices(&self, port: &MemAddr) -> Result<(), IoError> {
+        match self.journal.as_ref().map(Journal::devices_ahead) {
+            Some(step) if self.instrs_fetes.total() <= step => Err(IoError::Undone { port: port.clone(), step }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record that `value` is about to be written at `addr`
+    fn record_write(&mut self, addr: &MemAddr, value: i8) {
+        let old = self.memory.get_byte(addr);
+        self.effects.memory_writes.push((addr.clone(), old, value));
+    }
+
+    /// Translate a virtual address into a physical one. When there is no [Mmu], addresses are
+    /// already physical
+    fn translate(&self, addr: &MemAddr, access: Access) -> Result<MemAddr, Exception> {
+        match &self.mmu {
+            Some(mmu) => mmu.translate(addr, access),
+            None => Ok(addr.clone()),
+        }
+    }
+
+    /// IO port (and whether it's its high byte) that a physical address is mapped to, if any
+    fn mmio_port(&self, addr: &MemAddr) -> Option<(MemAddr, bool)> {
+        self.mmio.iter().find_map(|region| region.port(addr))
+    }
+
+    /// Run a TLB management instruction, which does nothing if the TLBs are disabled
+    fn with_mmu(&mut self, f: impl FnOnce(&mut Mmu, &Registers)) {
+        match &mut self.mmu {
+            Some(mmu) => f(mmu, &self.regs),
+            None => print_info("The TLBs are disabled, so this instruction does nothing"),
+        }
+    }
+
+    fn is_privileged(&self, inst: &Instruction) -> bool {
+        let verb = &&*inst.get_verb();
+        INSTRUCTS_PRIVILEGED.contains(verb) || (self.protect_io && INSTRUCTS_IO.contains(verb))
     }
 
     /// Execute the next instruction, which is the one that the Program  Counter is currently
-    /// pointing to. If there is no instruction at that address, the program gracefully exits.
-    pub fn execute_next(&mut self, print_status: bool) {
+    /// pointing to. If there is no instruction at that address, the program gracefully halts. The
+    /// reason why it halted is returned as an error.
+    ///
+    /// With the journal enabled (see [set_journal](Processador::set_journal)), what the
+    /// instruction changes is remembered so that it can be undone with
+    /// [step_back](Processador::step_back). An instruction that has been undone is then redone
+    /// from the journal, so the IO devices aren't asked again.
+    pub fn execute_next(&mut self, print_status: bool) -> Result<(), Halt> {
+        if let Some(entry) = self.journal.as_mut().and_then(Journal::redo) {
+            self.redo(entry);
+            if print_status { println!("{self}"); }
+            return Ok(());
+        }
+        if self.journal.is_none() { return self.run_next(print_status); }
+
+        let (pc, count, regs, sys_regs, mmu, outputs) =
+            (self.pc.clone(), self.instrs_fetes.clone(), self.regs.0, self.sys_regs.0, self.mmu.clone(), self.output_log.len());
+        let result = self.run_next(print_status);
+        if self.instrs_fetes.total() != count.total() {
+            let changed = |before: [Reg; 8], after: &Registers| (0..8)
+                .filter(|&i| before[i].0 != after.0[i].0)
+                .map(|i| (i as u8, before[i].0, after.0[i].0))
+                .collect();
+            let entry = JournalEntry {
+                pc: (pc, self.pc.clone()),
+                count: (count, self.instrs_fetes.clone()),
+                registers: changed(regs, &self.regs),
+                system_registers: changed(sys_regs, &self.sys_regs),
+                mmu: mmu.zip(self.mmu.clone()).filter(|(old, new)| old != new),
+                outputs: self.output_log[outputs..].to_vec(),
+                effects: self.effects.clone(),
+            };
+            if let Some(journal) = &mut self.journal { journal.push(entry); }
+        }
+        result
+    }
+
+    /// Undo the last instruction executed, as remembered by the journal. Returns whether there was
+    /// one to undo. The IO devices are left as they are, but going forward again gives the same
+    /// values the first time did, without using them. Changing the state by hand after undoing an
+    /// instruction that used them means that they can't be used until the program gets past it
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.as_mut().and_then(Journal::undo) else { return false };
+        self.pc = entry.pc.0;
+        self.instrs_fetes = entry.count.0;
+        entry.registers.iter().for_each(|&(i, old, _)| self.regs.0[i as usize].0 = old);
+        entry.system_registers.iter().for_each(|&(i, old, _)| self.sys_regs.0[i as usize].0 = old);
+        if let Some((old, _)) = &entry.mmu { self.mmu = Some(old.clone()); }
+        for (addr, old, _) in entry.effects.memory_writes.iter().rev() {
+            match old {
+                Some(old) => self.memory.insert_byte(addr, *old),
+                None => self.memory.remove_byte(addr),
+            }
+        }
+        self.output_log.truncate(self.output_log.len() - entry.outputs.len());
+        self.effects = self.journal.as_ref().and_then(Journal::last).map(|e| e.effects.clone()).unwrap_or_default();
+        // The states seen after this one are in the future now
+        if self.loop_detector.is_some() { self.loop_detector = Some(LoopDetector::default()); }
+        true
+    }
+
+    /// Address of the last instruction executed, if the journal remembers it
+    pub fn last_pc(&self) -> Option<&ProgCounter> {
+        self.journal.as_ref().and_then(Journal::last).map(|e| &e.pc.0)
+    }
+
+    /// Go forward again through an instruction that had been undone
+    fn redo(&mut self, entry: JournalEntry) {
+        self.pc = entry.pc.1;
+        self.instrs_fetes = entry.count.1;
+        entry.registers.iter().for_each(|&(i, _, new)| self.regs.0[i as usize].0 = new);
+        entry.system_registers.iter().for_each(|&(i, _, new)| self.sys_regs.0[i as usize].0 = new);
+        if let Some((_, new)) = entry.mmu { self.mmu = Some(new); }
+        entry.effects.memory_writes.iter().for_each(|(addr, _, new)| self.memory.insert_byte(addr, *new));
+        self.output_log.extend(entry.outputs);
+        self.effects = entry.effects;
+    }
+
+    fn run_next(&mut self, print_status: bool) -> Result<(), Halt> {
         print_info(&format!("Executing instruction at PC = {}", self.pc));
-        let inst = self.instr_memory.get(&(self.pc.0 as i16).into());
+        self.effects = Effects::default();
+        let addr = match self.translate(&self.pc.clone().into(), Access::Fetch) {
+            Ok(addr) => addr,
+            Err(e) => return self.raise(e),
+        };
+        let inst = self.instr_memory.get(&addr);
         let inst = match inst {
             Some(i) => i.clone(),
-            None => {
-                println!("The number of instructions done is: {:?}", self.instrs_fetes);
-                println!("There was no instruction to read when the PC = {} (dec '{}'), so the simulation has shut down 'gracefully' (for some definition of 'gracefully')",
-                    self.pc, self.pc.0);
-                std::process::exit(0);
-            },
+            None => return Err(Halt::NoInstruction { pc: self.pc.clone() }),
         };
+        let old_pc = self.pc.clone();
         self.pc.advance();
-        self.execute_raw(&inst);
+        match self.execute_raw(&inst) {
+            Ok(()) => {},
+            Err(Fault::Exception(e)) => {
+                // Faulting instructions are retried after the handler is done, system calls are not
+                if !matches!(e, Exception::SystemCall { .. }) { self.pc = old_pc.clone(); }
+                self.raise(e)?;
+            },
+            Err(Fault::Io(error)) => {
+                self.pc = old_pc.clone();
+                return Err(Halt::Io { error, pc: old_pc });
+            },
+            Err(Fault::Uninitialized(addr)) => {
+                self.pc = old_pc.clone();
+                return Err(Halt::UninitializedMemory { addr, pc: old_pc });
+            },
+        }
+        self.io.tick(&self.instrs_fetes.clock());
         if print_status { println!("{self}"); }
+
+        // Only jumping backwards can make the program go through the same state twice
+        if self.pc.0 <= old_pc.0 {
+            let hash = self.state_hash();
+            let now = LoopPoint { count: self.instrs_fetes.clone(), outputs: self.output_log.len() };
+            if let Some(previous) = self.loop_detector.as_mut().and_then(|d| d.check(hash, now.clone())) {
+                // Only the changes to the ports that the loop reads can get it out of it
+                let changes: Vec<_> = self.io.scheduled_changes().into_iter()
+                    .filter(|(port, _)| self.last_reads.get(port).is_some_and(|&read| read > previous.count.total()))
+                    .map(|(_, trigger)| trigger)
+                    .collect();
+                if changes.is_empty() {
+                    return Err(Halt::NonTerminatingLoop { pc: self.pc.clone(), length: now.count.total() - previous.count.total() });
+                }
+                // Skipping ahead would lose whatever the loop writes
+                if now.outputs == previous.outputs { self.fast_forward(&previous.count, &changes); }
+            }
+        }
+        Ok(())
+    }
+
+    /// The program has gone around a loop (that started after `previous` instructions) without
+    /// changing anything but the time, so it's waiting for one of the scheduled IO `changes`. Skip
+    /// as many times around the loop as possible without reaching any of them, counting the
+    /// instructions (and cycles) that would have been executed
+    fn fast_forward(&mut self, previous: &NumInstruccions, changes: &[Trigger]) {
+        let per_iteration = NumInstruccions {
+            fast: self.instrs_fetes.fast - previous.fast,
+            slow: self.instrs_fetes.slow - previous.slow,
+        };
+        let iteration = per_iteration.clock();
+        let now = self.instrs_fetes.clock();
+        let skipped = changes.iter().filter_map(|trigger| match trigger {
+            Trigger::Step(step)   => Some(step.saturating_sub(now.step + 1) / iteration.step),
+            Trigger::Cycle(cycle) => Some(cycle.saturating_sub(now.cycle + 1) / iteration.cycle),
+            Trigger::Reads(_)     => None,
+        }).min().unwrap_or(0);
+        if skipped == 0 { return; }
+
+        self.instrs_fetes.fast += skipped * per_iteration.fast;
+        self.instrs_fetes.slow += skipped * per_iteration.slow;
+        print_info(&format!("Waiting for a scheduled IO change: skipping {skipped} times around the loop ({} instructions, {} cycles)",
+            skipped * iteration.step, skipped * iteration.cycle));
+        self.io.tick(&self.instrs_fetes.clock());
+    }
+
+    /// Jump to the handler of the given [Exception], entering system mode with interrupts
+    /// disabled. The previous PSW is saved in `S0`, the address to return to in `S1`, the
+    /// exception code in `S2` and its argument in `S3`, so that the handler can use them and `RETI`
+    /// can go back. If there is no handler, the processor halts.
+    fn raise(&mut self, e: Exception) -> Result<(), Halt> {
+        let handler = match e {
+            Exception::SystemCall { .. } => self.syscall_handler.clone().or(self.exception_handler.clone()),
+            _ => self.exception_handler.clone(),
+        };
+        let Some(handler) = handler else {
+            return Err(Halt::UnhandledException { exception: e, pc: self.pc.clone() });
+        };
+
+        if !is_quiet() { println!("[EXCEPTION]: {e} (exception code {}), jumping to handler at {handler}", e.code()); }
+        self.sys_regs[&SAVED_PSW] = self.sys_regs[&PSW];
+        self.sys_regs[&RETURN_ADDR].0 = self.pc.0 as i16;
+        self.sys_regs[&EXCEPTION_CODE].0 = e.code() as i16;
+        self.sys_regs[&EXCEPTION_ARG].0 = e.argument();
+        self.sys_regs[&PSW].0 = PSW_SYSTEM_MODE;
+        self.pc = handler;
+        Ok(())
+    }
+
+    /// Hash of everything that determines what the program will do from now on: the PC, the
+    /// registers, the memory, the TLBs and the IO inputs
+    fn state_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.pc.0.hash(&mut hasher);
+        self.regs.0.iter().for_each(|r| r.0.hash(&mut hasher));
+        self.sys_regs.0.iter().for_each(|r| r.0.hash(&mut hasher));
+
+        self.memory.digest().hash(&mut hasher);
+
+        self.io.hash_state(&mut hasher);
+
+        if let Some(mmu) = &self.mmu {
+            for e in mmu.instructions.entries().iter().chain(mmu.data.entries()) {
+                (e.virtual_tag, e.physical_tag, e.read_only, e.present, e.valid).hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Number of instructions that have been executed so far
+    pub fn instruction_count(&self) -> &NumInstruccions { &self.instrs_fetes }
+    /// What the last instruction executed did to the registers, the memory and the IO ports
+    pub fn last_effects(&self) -> &Effects { &self.effects }
+    /// Every value written with `OUT` so far, in order
+    pub fn output_log(&self) -> &[OutputRecord] { &self.output_log }
+    /// Whether to halt when the program goes through the exact same state twice, which means that
+    /// it will never terminate. Enabled by default
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = enabled.then(LoopDetector::default);
+    }
+    /// Address of the next instruction to execute
+    pub fn pc(&self) -> &ProgCounter { &self.pc }
+    /// Jump to another instruction
+    pub fn set_pc(&mut self, pc: ProgCounter) { self.forget_undone(); self.pc = pc; }
+    /// Value of the register `Ri`
+    pub fn register(&self, i: u8) -> i16 { self.regs[&RegLabel(i)].0 }
+    /// Change the value of the register `Ri`
+    pub fn set_register(&mut self, i: u8, value: i16) { self.forget_undone(); self.regs[&RegLabel(i)].0 = value; }
+    /// Value of the system register `Si`
+    pub fn system_register(&self, i: u8) -> i16 { self.sys_regs[&RegLabel(i)].0 }
+    /// The data memory
+    pub fn memory(&self) -> &Memory { &self.memory }
+    /// The data memory, to change it
+    pub fn memory_mut(&mut self) -> &mut Memory { self.forget_undone(); &mut self.memory }
+    /// Keep a journal of what every instruction changes, so that it can be undone with
+    /// [step_back](Processador::step_back). Disabled by default
+    pub fn set_journal(&mut self, enabled: bool) { self.journal = enabled.then(Journal::default); }
+    /// Changing the state by hand means that the instructions undone can't be redone
+    fn forget_undone(&mut self) {
+        if let Some(journal) = &mut self.journal { journal.forget_undone(); }
+    }
+    /// The instruction at the given address (as is, without going through the TLBs)
+    pub fn instruction_at(&self, pc: &ProgCounter) -> Option<&Instruction> {
+        self.instr_memory.get(&pc.clone().into())
+    }
+    /// Current value of the IO ports whose devices can tell without being read (see
+    /// [IoDevice::port_values])
+    pub fn io_ports(&self) -> Vec<(MemAddr, Value16Bit)> { self.io.port_values() }
+    /// Enable address translation through the given [Mmu], or disable it with `None`. Disabled
+    /// by default
+    pub fn set_mmu(&mut self, mmu: Option<Mmu>) { self.mmu = mmu; }
+    /// The [Mmu], if address translation is enabled
+    pub fn mmu(&self) -> Option<&Mmu> { self.mmu.as_ref() }
+    /// Set where to jump to when an exception is raised. Without one, exceptions stop the
+    /// simulation
+    pub fn set_exception_handler(&mut self, handler: Option<ProgCounter>) { self.exception_handler = handler; }
+    /// Set where `CALLS` jumps to. Without one, it uses the exception handler
+    pub fn set_syscall_handler(&mut self, handler: Option<ProgCounter>) { self.syscall_handler = handler; }
+    /// Set which instructions may be executed. Any other one raises an exception
+    pub fn set_profile(&mut self, profile: IsaProfile) { self.profile = profile; }
+    /// Whether `IN` and `OUT` are privileged instructions. They are not by default
+    pub fn set_protect_io(&mut self, protect_io: bool) { self.protect_io = protect_io; }
+    /// Current privilege mode, as given by the PSW
+    pub fn mode(&self) -> Mode {
+        if self.sys_regs[&PSW].0 & PSW_SYSTEM_MODE != 0 { Mode::System } else { Mode::User }
+    }
+    /// Change the privilege mode. Programs start in system mode
+    pub fn set_mode(&mut self, mode: Mode) {
+        match mode {
+            Mode::System => self.sys_regs[&PSW].0 |= PSW_SYSTEM_MODE,
+            Mode::User   => self.sys_regs[&PSW].0 &= !PSW_SYSTEM_MODE,
+        }
+    }
+    /// Replace the fixed values of the IO's ports (the ones given by the IO file). Devices that
+    /// change by themselves are better modelled with [attach_device](Processador::attach_device)
+    pub fn update_io(&mut self, new_io: HashMap<MemAddr, Value16Bit>) {
+        self.io.set_ports(new_io);
+    }
+    /// Attach an [IoDevice] to the given (inclusive) range of ports, so that `IN` and `OUT` on them
+    /// reach it
+    pub fn attach_device(&mut self, ports: RangeInclusive<i16>, device: Box<dyn IoDevice>) {
+        self.io.attach(ports, device);
+    }
+    /// Watch a region of the memory with a [MemoryHook], which is told about every `ST`/`STB` there
+    pub fn add_memory_hook(&mut self, region: RangeInclusive<u16>, hook: Box<dyn MemoryHook>) {
+        self.memory.add_hook(region, hook);
+    }
+    /// Hand a region of the memory over to the IO bus, so that `LD`/`LDB`/`ST`/`STB` there reach the
+    /// devices attached to it (see [MmioRegion])
+    pub fn map_io(&mut self, region: MmioRegion) { self.mmio.push(region); }
+    /// Set the [IoDevice] that `IN` reads from when no attached device has a value for the port
+    pub fn set_io_fallback(&mut self, device: Option<Box<dyn IoDevice>>) {
+        self.io.set_fallback(device);
     }
-    /// Update the IO's ports. Pretty much unusable as it must be hard-coded in, but I can't really
-    /// think of an alternative that's useful
-    pub fn update_io(&mut self, new_io: HashMap<MemAddr, Value16Bit>) { self.io = IOSystem(new_io); }
 }
 
-/// The sequence of eight registers that are contained in the [Processador]'s REGFILE.
+/// The sequence of eight registers that are contained in the [Processador]'s REGFILE. The system
+/// registers (`S0`-`S7`) are held in another one.
 pub struct Registers([Reg; 8]);
 
+const SAVED_PSW: RegLabel = RegLabel(0);
+const RETURN_ADDR: RegLabel = RegLabel(1);
+const EXCEPTION_CODE: RegLabel = RegLabel(2);
+const EXCEPTION_ARG: RegLabel = RegLabel(3);
+const PSW: RegLabel = RegLabel(7);
+
+/// Privilege mode of the [Processador]. Privileged instructions raise an exception in user mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Mode {
+    User,
+    System,
+}
+
+impl Registers {
+    /// The system registers at startup: everything is 0 except for the PSW, which is in system mode
+    fn system() -> Self {
+        let mut regs = Self::default();
+        regs[&PSW].0 = PSW_SYSTEM_MODE;
+        regs
+    }
+}
+
 /// The held memory that is contained in the [Processador]'s MEMORY module, stored as bytes (not
-/// words). 
-#[derive(Debug, Clone, Default)] 
-pub struct Memory(HashMap<MemAddr, MemValue>);
+/// words). Regions of it may be watched by [MemoryHook]s, which are told about every byte written
+/// there. A clone has the same contents, but isn't watched by any of them (see its [Clone] impl).
+#[derive(Debug, Default)] 
+pub struct Memory(HashMap<MemAddr, MemValue>, MemoryHooks, u64);
+
+/// Something that wants to know when a region of the [Memory] changes, like a
+/// [Framebuffer](crate::devices::Framebuffer)
+pub trait MemoryHook {
+    /// Called after `value` has been stored at `addr`
+    fn write(&mut self, addr: &MemAddr, value: i8);
+}
+
+/// The [MemoryHook]s of a [Memory] and the regions they watch
+#[derive(Default)]
+struct MemoryHooks(Vec<(RangeInclusive<u16>, Box<dyn MemoryHook>)>);
+
+impl fmt::Debug for MemoryHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.iter().map(|(region, _)| region)).finish()
+    }
+}
+
+impl Clone for Memory {
+    /// Copy the contents, but none of the [MemoryHook]s: they are watching the memory they were
+    /// added to, not its copies, so writing to the clone doesn't tell them anything
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), MemoryHooks::default(), self.2)
+    }
+}
 
 impl Memory {
     /// Create a new empty memory
     pub fn new() -> Self {
-        Self(HashMap::new())
+        Self::default()
+    }
+    /// Watch the given (inclusive) region with a [MemoryHook]
+    pub fn add_hook(&mut self, region: RangeInclusive<u16>, hook: Box<dyn MemoryHook>) {
+        self.1.0.push((region, hook));
     }
     /// Insert a byte at the given address
     pub fn insert_byte(&mut self, addr: &MemAddr, val: i8) {
-        let _ = self.0.insert(addr.clone(), MemValue(val));
+        if let Some(old) = self.0.insert(addr.clone(), MemValue(val)) {
+            self.2 = self.2.wrapping_sub(byte_hash(addr, old.0));
+        }
+        self.2 = self.2.wrapping_add(byte_hash(addr, val));
+        self.1.0.iter_mut()
+            .filter(|(region, _)| region.contains(&(addr.0 as u16)))
+            .for_each(|(_, hook)| hook.write(addr, val));
+    }
+    /// Forget the byte at the given address, as if it had never been written. The hooks see a 0
+    pub(crate) fn remove_byte(&mut self, addr: &MemAddr) {
+        if let Some(old) = self.0.remove(addr) {
+            self.2 = self.2.wrapping_sub(byte_hash(addr, old.0));
+        }
+        self.1.0.iter_mut()
+            .filter(|(region, _)| region.contains(&(addr.0 as u16)))
+            .for_each(|(_, hook)| hook.write(addr, 0));
     }
     /// Insert a word at the given address in Little Endian: the even slot has the LSB and
     /// the even slot + 1 has MSB. Also note the alignment: if the address is odd, it will become
     /// even by truncation (`addr && !-1`)
     pub fn insert_word(&mut self, addr: &MemAddr, val: i16) {
-        let high = ((val & 0xFF00u16 as i16) >> 7) as i8;
+        let high = (val >> 8) as i8;
         let low = (val & 0x00FF) as i8;
         let addr = addr.align();
 
-        self.0.insert(addr.clone(), MemValue(low));
-        self.0.insert(MemAddr(addr.0 + 1), MemValue(high));
+        self.insert_byte(&addr, low);
+        self.insert_byte(&MemAddr(addr.0 + 1), high);
     }
+    /// A hash of the contents, which doesn't depend on the order they were written in. It's kept
+    /// up to date with every write, so getting it doesn't go through the whole memory
+    pub(crate) fn digest(&self) -> u64 { self.2 }
     /// Get stored byte from the given memory address
     pub fn get_byte(&self, addr: &MemAddr) -> Option<i8> {
         self.0.get(addr).map(|m| m.0)
@@ -143,13 +638,20 @@ impl Memory {
     pub fn get_word(&self, addr: &MemAddr) -> Option<i16> {
         let addr = addr.align();
 
-        let low = self.0.get(&addr)?.0 as i16;
+        let low = self.0.get(&addr)?.0 as u8 as i16;
         let high = self.0.get(&MemAddr(addr.0 + 1))?.0 as i16;
-        let out = (high << 7) | low;
+        let out = (high << 8) | low;
         Some(out)
     }
 }
 
+/// What a byte adds to the [digest](Memory::digest) of the memory
+fn byte_hash(addr: &MemAddr, val: i8) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (addr.0, val).hash(&mut hasher);
+    hasher.finish()
+}
+
 impl MemAddr {
     /// Returns new address, but aligned instead. Does not require mutable access and instead
     /// returns the new value for better ergonomics when dealing with immutable addrs.
@@ -179,19 +681,6 @@ impl IndexMut<&RegLabel> for Registers {
     }
 }
 
-/// The currently held values from the INPUT system. The output system is a rudimentary printing
-/// out of the value and the intended address
-pub struct IOSystem(HashMap<MemAddr, Value16Bit>);
-
-impl IOSystem {
-    fn get(&self, index: &MemAddr) -> Option<&Value16Bit> {
-        self.0.get(index)
-    }
-    fn _get_mut(&mut self, index: &MemAddr) -> Option<&mut Value16Bit> {
-        self.0.get_mut(index)
-    }
-}
-
 /// The main Processor type. This contains the entire state of the simulator at any given time 
 /// and implements all the functionality that is given.
 ///
@@ -205,16 +694,118 @@ pub struct Processador {
     instr_memory: Instructions,
     pc: ProgCounter,
     instrs_fetes: NumInstruccions,
+    mmu: Option<Mmu>,
+    sys_regs: Registers,
+    exception_handler: Option<ProgCounter>,
+    syscall_handler: Option<ProgCounter>,
+    protect_io: bool,
+    profile: IsaProfile,
+    loop_detector: Option<LoopDetector>,
+    output_log: Vec<OutputRecord>,
+    mmio: Vec<MmioRegion>,
+    effects: Effects,
+    journal: Option<Journal>,
+    /// Number of instructions executed when each port was last read
+    last_reads: HashMap<MemAddr, usize>,
+}
+
+/// What an instruction did to the registers, the memory and the IO ports, as recorded by
+/// [execute_raw](Processador::execute_raw). Memory accesses that went to the IO bus are recorded as
+/// port accesses
+#[derive(Debug, Clone, Default)]
+pub struct Effects {
+    /// General purpose registers read
+    pub register_reads: Vec<u8>,
+    /// General purpose registers written: which one, with its old and new value
+    pub register_writes: Vec<(u8, i16, i16)>,
+    /// Bytes of memory read
+    pub memory_reads: Vec<MemAddr>,
+    /// Bytes of memory written: where, with its old value (if it had one) and its new one
+    pub memory_writes: Vec<(MemAddr, Option<i8>, i8)>,
+    /// IO ports read, with the value they gave
+    pub io_reads: Vec<(MemAddr, i16)>,
+    /// IO ports written, with the value written
+    pub io_writes: Vec<(MemAddr, i16)>,
+}
+
+/// Why the [Processador] stopped executing, as returned by
+/// [execute_next](Processador::execute_next)
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Halt {
+    #[error("there was no instruction to read when the PC = {pc} (dec '{}'), so the simulation has shut down 'gracefully' (for some definition of 'gracefully')", pc.0)]
+    NoInstruction { pc: ProgCounter },
+    #[error("non-terminating loop detected at PC={pc}, cycle of length {length}")]
+    NonTerminatingLoop { pc: ProgCounter, length: usize },
+    #[error("[EXCEPTION]: {exception} (exception code {}) at PC = {pc}", exception.code())]
+    UnhandledException { exception: Exception, pc: ProgCounter },
+    #[error("[IO]: {error} at PC = {pc}")]
+    Io { error: IoError, pc: ProgCounter },
+    #[error("tried to read uninitialized memory at 0x{:0>4X} at PC = {pc}", addr.0)]
+    UninitializedMemory { addr: MemAddr, pc: ProgCounter },
+}
+
+/// Why an instruction couldn't be completed: an [Exception], which the program may handle, or an
+/// error that stops the simulation
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Fault {
+    #[error(transparent)]
+    Exception(#[from] Exception),
+    #[error(transparent)]
+    Io(#[from] IoError),
+    #[error("tried to read uninitialized memory at 0x{:0>4X}", .0.0)]
+    Uninitialized(MemAddr),
+}
+
+impl Halt {
+    /// Exit code of the simulator when it halts for this reason: 0 only if the program ran out of
+    /// instructions, as it's meant to
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Halt::NoInstruction { .. } => 0,
+            Halt::UnhandledException { .. } | Halt::Io { .. } | Halt::UninitializedMemory { .. } => 1,
+            Halt::NonTerminatingLoop { .. } => 2,
+        }
+    }
+
+    /// The instruction it halted at, if there was one
+    pub fn pc(&self) -> Option<&ProgCounter> {
+        match self {
+            Halt::NoInstruction { .. } => None,
+            Halt::NonTerminatingLoop { pc, .. } | Halt::UnhandledException { pc, .. }
+            | Halt::Io { pc, .. } | Halt::UninitializedMemory { pc, .. } => Some(pc),
+        }
+    }
 }
 
+/// How many instructions of each kind have been executed. Slow instructions are the ones that
+/// access memory
 #[derive(Clone, Debug, Default)]
-struct NumInstruccions {
-    fast: usize,
-    slow: usize,
+pub struct NumInstruccions {
+    /// Instructions that don't access memory
+    pub fast: usize,
+    /// Instructions that access memory (LD/LDB/ST/STB)
+    pub slow: usize,
+}
+
+impl NumInstruccions {
+    /// Cycles taken by a fast instruction: fetch, decode and execute
+    pub const FAST_CYCLES: usize = 3;
+    /// Cycles taken by a slow instruction: fetch, decode, address computation and memory access
+    pub const SLOW_CYCLES: usize = 4;
+
+    /// Total number of instructions executed
+    pub fn total(&self) -> usize { self.fast + self.slow }
+    /// Total number of cycles elapsed
+    pub fn cycles(&self) -> usize { self.fast * Self::FAST_CYCLES + self.slow * Self::SLOW_CYCLES }
+    /// The current time, in steps and cycles
+    pub fn clock(&self) -> Clock { Clock { step: self.total(), cycle: self.cycles() } }
 }
 
 #[rustfmt::skip] 
 #[derive(Clone, Copy)]                    pub struct Reg(pub i16);
+/// An address, either of the memory or of an IO port
 #[derive(Hash, PartialEq, Eq, Clone)] pub struct MemAddr(pub i16);
 #[derive(Clone)]                     pub struct MemValue(pub i8);
 #[derive(Clone)]                    pub struct MemOffset(pub i16);
@@ -223,6 +814,7 @@ struct NumInstruccions {
 #[derive(Clone)]                  pub struct ProgCounter(pub u16);
 #[derive(Clone)]                   pub struct ImmediateN6(pub i8);
 #[derive(Clone)]                   pub struct ImmediateN8(pub i8);
+/// A value read from or written to an IO port
 #[derive(Clone)]                   pub struct Value16Bit(pub i16);
 #[derive(Debug, Clone)]              pub struct RegLabel(pub u8);
 
@@ -241,6 +833,7 @@ impl From<u16> for ProgCounter {
     fn from(value: u16) -> Self { Self(value) }
 }
 
+#[allow(missing_docs)]
 impl MemAddr {
     pub fn inc(&mut self)         { self.0 += 2; }
     pub fn inc_one(&mut self)     { self.0 += 1; }
@@ -305,7 +898,7 @@ try_from_str_i8!(MemValue, ImmediateN6, ImmediateN8);
 try_from_str_i16!(MemAddr, MemOffset);
 
 other_impls_2!(MemValue, ImmediateN6, ImmediateN8);
-other_impls_4!(MemAddr, MemOffset, ProgCounter);
+other_impls_4!(MemAddr, MemOffset, ProgCounter, Value16Bit);
 
 impl fmt::Display for Processador {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -315,7 +908,19 @@ impl fmt::Display for Processador {
             out.push_str(&format!("\x1b[1;4;31mR{i}: 0x{:0>4X}\x1b[0m,  ", reg.0));
         }
         out.push('\n');
+        out.push_str(&format!("- Mode: {} (PSW: 0x{:0>4X})\n", match self.mode() {
+            Mode::User => "USER", Mode::System => "SYSTEM"
+        }, self.sys_regs[&PSW].0));
+        out.push_str("- System regs: ");
+        for (i, reg) in self.sys_regs.0.iter().enumerate() {
+            out.push_str(&format!("S{i}: 0x{:0>4X},  ", reg.0));
+        }
+        out.push('\n');
         out.push_str(&format!("- Memory: \x1b[1;4;34m{:}\x1b[0m", self.memory));
+        if let Some(mmu) = &self.mmu {
+            out.push_str(&format!("\n- iTLB: {}", mmu.instructions));
+            out.push_str(&format!("\n- dTLB: {}", mmu.data));
+        }
         out.push_str("\n[-------END_STATUS-------]\n\n\n\n\n");
 
         write!(f, "{out}")
@@ -356,3 +961,184 @@ fn se_8(n: i8) -> i16 {
     val
 }
 
+
+#[test]
+fn test_privilege_modes() {
+    use crate::read_instructions;
+
+    crate::set_quiet(true);
+    let cpu = |program: &str, mode: Mode| {
+        let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+        cpu.set_mode(mode);
+        cpu
+    };
+    let protected = |result: Result<(), Halt>| matches!(result,
+        Err(Halt::UnhandledException { exception: Exception::ProtectedInstruction { .. }, pc: ProgCounter(0) })
+    );
+
+    // Privileged instructions only run in system mode, which is where programs start
+    assert_eq!(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), Instructions::new(), HashMap::new()).mode(), Mode::System);
+    let mut system = cpu("EI", Mode::System);
+    system.execute_next(false).unwrap();
+    assert_eq!(system.system_register(7), PSW_SYSTEM_MODE | PSW_INTERRUPTS_ENABLED);
+    assert!(protected(cpu("EI", Mode::User).execute_next(false)));
+
+    // With a handler, the faulting instruction is retried after it
+    let mut user = cpu("WRS S5, R1\nNOP\nRDS R2, S2", Mode::User);
+    user.set_exception_handler(Some(ProgCounter(4)));
+    user.execute_next(false).unwrap();
+    assert_eq!((user.pc().0, user.mode(), user.system_register(1)), (4, Mode::System, 0));
+    user.execute_next(false).unwrap();
+    assert_eq!(user.register(2), 13);
+
+    // CALLS goes to the system call handler, and RETI goes back to the next instruction in user mode
+    let mut user = cpu("MOVI R1, 5\nCALLS R1\nMOVI R2, 1\nRDS R3, S3\nRETI", Mode::User);
+    user.set_syscall_handler(Some(ProgCounter(6)));
+    user.set_exception_handler(Some(ProgCounter(0x20)));
+    for _ in 0..2 { user.execute_next(false).unwrap(); }
+    assert_eq!((user.pc().0, user.mode(), user.system_register(1), user.system_register(2)), (6, Mode::System, 4, 14));
+    for _ in 0..2 { user.execute_next(false).unwrap(); }
+    assert_eq!((user.pc().0, user.mode(), user.register(3)), (4, Mode::User, 5));
+    user.execute_next(false).unwrap();
+    assert_eq!(user.register(2), 1);
+
+    // IN and OUT are only privileged with --protect-io
+    let mut user = cpu("OUT 1, R0", Mode::User);
+    user.execute_next(false).unwrap();
+    let mut user = cpu("OUT 1, R0", Mode::User);
+    user.set_protect_io(true);
+    assert!(protected(user.execute_next(false)));
+    let mut system = cpu("OUT 1, R0", Mode::System);
+    system.set_protect_io(true);
+    system.execute_next(false).unwrap();
+}
+
+#[test]
+fn test_stores() {
+    use crate::read_instructions;
+
+    // ST/STB take the address from Ra and the value from Rb, and STB only stores its low byte
+    let program = "MOVI R1, 0x10\nMOVI R2, 0x34\nMOVHI R2, 0x12\nST 2(R1), R2\nSTB 5(R1), R2\nLD R3, 2(R1)\nLDB R4, 5(R1)\nLDB R5, 4(R1)";
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+    crate::set_quiet(true);
+    for _ in 0..7 { cpu.execute_next(false).unwrap(); }
+    assert_eq!(cpu.memory().get_word(&MemAddr(0x12)), Some(0x1234));
+    assert_eq!(cpu.memory().get_byte(&MemAddr(0x15)), Some(0x34));
+    assert_eq!(cpu.memory().get_byte(&MemAddr(0x14)), None);
+    assert_eq!((cpu.register(3), cpu.register(4)), (0x1234, 0x34));
+    // Reading memory that was never written stops the simulation
+    let halt = cpu.execute_next(false).unwrap_err();
+    assert!(matches!(halt, Halt::UninitializedMemory { addr: MemAddr(0x14), pc: ProgCounter(14) }));
+    assert_eq!((halt.exit_code(), cpu.pc().0), (1, 14));
+
+    // The whole low byte is stored, bit 7 included, and ST to an odd address stores the word
+    // that contains it
+    let program = "MOVI R1, 0x11\nMOVI R2, 0xF0\nSTB 0(R1), R2\nLDB R3, 0(R1)\nST 2(R1), R2\nLD R4, 2(R1)";
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+    for _ in 0..6 { cpu.execute_next(false).unwrap(); }
+    assert_eq!(cpu.memory().get_byte(&MemAddr(0x11)), Some(0xF0u8 as i8));
+    assert_eq!((cpu.register(3), cpu.register(4)), (-16, -16));
+    assert_eq!(cpu.memory().get_word(&MemAddr(0x12)), Some(-16));
+}
+
+#[test]
+fn test_word_encoding() {
+    use std::{cell::Cell, rc::Rc};
+
+    // Words are split in two whole bytes, even with bit 7 (or 15) set
+    let mut memory = Memory::new();
+    for word in [0x0080, 0x00FF, 0x7F80, 0x1234, -1, i16::MIN] {
+        memory.insert_word(&MemAddr(0x20), word);
+        assert_eq!(memory.get_word(&MemAddr(0x20)), Some(word));
+        assert_eq!(memory.get_byte(&MemAddr(0x21)), Some((word >> 8) as i8));
+    }
+
+    // A clone has the same contents, but writing to it isn't seen by the hooks
+    struct Counter(Rc<Cell<usize>>);
+    impl MemoryHook for Counter {
+        fn write(&mut self, _: &MemAddr, _: i8) { self.0.set(self.0.get() + 1); }
+    }
+    let writes = Rc::new(Cell::new(0));
+    memory.add_hook(0x20..=0x21, Box::new(Counter(writes.clone())));
+    let mut copy = memory.clone();
+    assert_eq!(copy.get_word(&MemAddr(0x20)), Some(i16::MIN));
+    copy.insert_word(&MemAddr(0x20), 1);
+    assert_eq!(writes.get(), 0);
+    memory.insert_word(&MemAddr(0x20), 1);
+    assert_eq!(writes.get(), 2);
+}
+
+#[test]
+fn test_step_back_devices() {
+    use crate::{read_instructions, devices::Console};
+
+    let program = "MOVI R1, 0x41\nOUT 5, R1\nMOVI R2, 3\nMOVI R3, 0x25\nWRPD R2, R3";
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+    let console = Console::new(MemAddr(5));
+    let text = console.buffer();
+    cpu.attach_device(5..=5, Box::new(console));
+    cpu.set_mmu(Some(Mmu::default()));
+    cpu.set_journal(true);
+    crate::set_quiet(true);
+    for _ in 0..5 { cpu.execute_next(false).unwrap(); }
+
+    // The TLB write is undone and redone like the rest
+    let written = cpu.mmu().cloned();
+    assert_ne!(written, Some(Mmu::default()));
+    assert!(cpu.step_back());
+    assert_eq!(cpu.mmu(), Some(&Mmu::default()));
+    cpu.execute_next(false).unwrap();
+    assert_eq!(cpu.mmu(), written.as_ref());
+
+    // Going forward over an OUT again doesn't write to the console twice...
+    for _ in 0..5 { assert!(cpu.step_back()); }
+    for _ in 0..2 { cpu.execute_next(false).unwrap(); }
+    assert_eq!(*text.borrow(), "A");
+    // ...and once the state is changed by hand, it can't be run again
+    for _ in 0..2 { cpu.step_back(); }
+    cpu.set_register(1, 0x42);
+    cpu.execute_next(false).unwrap();
+    assert!(matches!(cpu.execute_next(false), Err(Halt::Io { error: IoError::Undone { step: 2, .. }, pc: ProgCounter(2) })));
+    assert_eq!(*text.borrow(), "A");
+}
+
+#[test]
+fn test_mmio_bytes() {
+    use crate::{read_instructions, io::IoError};
+
+    /// Ports that hold whatever was last written to them
+    struct Latch(HashMap<MemAddr, Value16Bit>);
+    impl IoDevice for Latch {
+        fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> { Ok(self.0.get(port).cloned()) }
+        fn write(&mut self, port: &MemAddr, value: Value16Bit) { self.0.insert(port.clone(), value); }
+        fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> { self.0.iter().map(|(p, v)| (p.clone(), v.clone())).collect() }
+    }
+
+    // Ports 4 and 5 are at 0x7FFE and 0x8000, across the sign of the addresses
+    let program = "MOVI R2, 0x34\nMOVHI R2, 0x12\nST 0(R1), R2\nST 2(R1), R2\n\
+        MOVI R3, 0x56\nSTB 1(R1), R3\nSTB 2(R1), R3\nIN R4, 4\nIN R5, 5";
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(), HashMap::new());
+    cpu.set_register(1, 0x7FFE);
+    cpu.attach_device(4..=5, Box::new(Latch(HashMap::new())));
+    cpu.map_io("0x7FFE-0x8001=4".parse().unwrap());
+    crate::set_quiet(true);
+    for _ in 0..9 { cpu.execute_next(false).unwrap(); }
+    assert_eq!((cpu.register(4), cpu.register(5)), (0x5634, 0x1256));
+    assert!(cpu.memory().get_byte(&MemAddr(0x8000u16 as i16)).is_none());
+}
+
+#[test]
+fn test_memory_digest() {
+    let (mut a, mut b) = (Memory::new(), Memory::new());
+    a.insert_byte(&MemAddr(2), 5);
+    a.insert_byte(&MemAddr(4), 7);
+    b.insert_byte(&MemAddr(4), 1);
+    b.insert_byte(&MemAddr(2), 5);
+    assert_ne!(a.digest(), b.digest());
+    b.insert_byte(&MemAddr(4), 7);
+    assert_eq!(a.digest(), b.digest());
+    a.remove_byte(&MemAddr(4));
+    b.remove_byte(&MemAddr(4));
+    b.remove_byte(&MemAddr(6));
+    assert_eq!(a.digest(), b.digest());
+}
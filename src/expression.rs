@@ -0,0 +1,305 @@
+use crate::{execute::{Processador, MemAddr}, preprocessor::Labels, parse_value};
+
+/// Errors in an [Expression]
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum ExprError {
+    #[error("unexpected '{0}'")]
+    Unexpected(String),
+    #[error("the expression ended too soon")]
+    UnexpectedEnd,
+    #[error("'{0}' is neither a register, a label nor a number")]
+    UnknownName(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("'new' only has a value in the condition of a watchpoint")]
+    NoNewValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or, And,
+    Eq, Ne, Lt, Le, Gt, Ge,
+    Add, Sub,
+    Mul, Div, Rem,
+}
+
+impl Op {
+    /// How tightly it binds: operators with a higher precedence are applied first
+    fn precedence(&self) -> u8 {
+        match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::Add | Op::Sub => 4,
+            Op::Mul | Op::Div | Op::Rem => 5,
+        }
+    }
+
+    fn apply(&self, a: i16, b: i16) -> Result<i16, ExprError> {
+        Ok(match self {
+            Op::Or  => (a != 0 || b != 0) as i16,
+            Op::And => (a != 0 && b != 0) as i16,
+            Op::Eq  => (a == b) as i16,
+            Op::Ne  => (a != b) as i16,
+            Op::Lt  => (a < b) as i16,
+            Op::Le  => (a <= b) as i16,
+            Op::Gt  => (a > b) as i16,
+            Op::Ge  => (a >= b) as i16,
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+            Op::Mul => a.wrapping_mul(b),
+            Op::Div => a.checked_div(b).ok_or(ExprError::DivisionByZero)?,
+            Op::Rem => a.checked_rem(b).ok_or(ExprError::DivisionByZero)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i16),
+    Register(u8),
+    SystemRegister(u8),
+    Pc,
+    Hits,
+    New,
+    /// The word (or the byte, if `false`) of memory at the address
+    Memory(Box<Expr>, bool),
+    Io(Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+/// What an [Expression] is evaluated against
+pub struct Context<'a> {
+    #[allow(missing_docs)]
+    pub cpu: &'a Processador,
+    /// Times the breakpoint (or watchpoint) has been reached, counting this one
+    pub hits: usize,
+    /// The value a watchpoint's target was just accessed with, if it was
+    pub value: Option<i16>,
+}
+
+/// An expression over the state of a [Processador], like `R3 == 0 && mem[v+4] > 10`, `hits > 5` or
+/// `io[1] != 0`. It may use:
+/// - numbers, written like everywhere else (`10`, `0x0A`, `'a'`)
+/// - the registers `R0`-`R7` and `S0`-`S7`, and `PC`
+/// - labels of either section, which stand for their address
+/// - `mem[addr]` and `byte[addr]`, the word or byte of memory there (0 if it hasn't been written)
+/// - `io[port]`, the value of an IO port (0 if its device can't tell without being read)
+/// - `hits`, the times a breakpoint or watchpoint has been reached
+/// - `new`, the value a watchpoint's target was just written (or read) with
+/// - `+`, `-`, `*`, `/`, `%`, comparisons (`==`, `!=`, `<`, `<=`, `>`, `>=`), `&&`, `||`, `!`
+///   and parentheses. Comparisons and logical operators give 1 or 0
+#[derive(Debug, Clone)]
+pub struct Expression {
+    text: String,
+    expr: Expr,
+}
+
+impl Expression {
+    /// Parse an expression, replacing its labels with their addresses
+    pub fn parse(text: &str, labels: &Labels) -> Result<Self, ExprError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0, labels };
+        let expr = parser.binary(0)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(ExprError::Unexpected(token.clone()));
+        }
+        Ok(Self { text: text.trim().to_string(), expr })
+    }
+
+    /// The value of the expression right now
+    pub fn eval(&self, context: &Context) -> Result<i16, ExprError> {
+        eval(&self.expr, context)
+    }
+
+    /// Whether the expression holds (isn't 0) right now
+    pub fn holds(&self, context: &Context) -> Result<bool, ExprError> {
+        Ok(self.eval(context)? != 0)
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { f.write_str(&self.text) }
+}
+
+fn eval(expr: &Expr, context: &Context) -> Result<i16, ExprError> {
+    let cpu = context.cpu;
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Register(i) => cpu.register(*i),
+        Expr::SystemRegister(i) => cpu.system_register(*i),
+        Expr::Pc => cpu.pc().0 as i16,
+        Expr::Hits => context.hits as i16,
+        Expr::New => context.value.ok_or(ExprError::NoNewValue)?,
+        Expr::Memory(addr, true) => cpu.memory().get_word(&MemAddr(eval(addr, context)?)).unwrap_or(0),
+        Expr::Memory(addr, false) => cpu.memory().get_byte(&MemAddr(eval(addr, context)?)).unwrap_or(0) as i16,
+        Expr::Io(port) => {
+            let port = eval(port, context)?;
+            cpu.io_ports().into_iter().find(|(p, _)| p.0 == port).map(|(_, v)| v.0).unwrap_or(0)
+        },
+        Expr::Not(e) => (eval(e, context)? == 0) as i16,
+        Expr::Neg(e) => eval(e, context)?.wrapping_neg(),
+        // Don't evaluate the right side if the left one already decides
+        Expr::Binary(Op::And, a, b) => (eval(a, context)? != 0 && eval(b, context)? != 0) as i16,
+        Expr::Binary(Op::Or, a, b) => (eval(a, context)? != 0 || eval(b, context)? != 0) as i16,
+        Expr::Binary(op, a, b) => op.apply(eval(a, context)?, eval(b, context)?)?,
+    })
+}
+
+/// Split the text into names and numbers, operators and brackets
+fn tokenize(text: &str) -> Result<Vec<String>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {},
+            '\'' => {
+                let (ch, end) = (chars.next(), chars.next());
+                match (ch, end) {
+                    (Some(ch), Some('\'')) => tokens.push(format!("'{ch}'")),
+                    _ => return Err(ExprError::Unexpected("'".into())),
+                }
+            },
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_' || c == '.') { break; }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(word);
+            },
+            '=' | '!' | '<' | '>' | '&' | '|' => {
+                let mut op = c.to_string();
+                if let Some(&next) = chars.peek() {
+                    if next == '=' || (next == c && (c == '&' || c == '|')) {
+                        op.push(next);
+                        chars.next();
+                    }
+                }
+                tokens.push(op);
+            },
+            '+' | '-' | '*' | '/' | '%' | '(' | ')' | '[' | ']' => tokens.push(c.to_string()),
+            c => return Err(ExprError::Unexpected(c.to_string())),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    labels: &'a Labels,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<&str, ExprError> {
+        let token = self.tokens.get(self.pos).ok_or(ExprError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), ExprError> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(ExprError::Unexpected(token.into())),
+        }
+    }
+
+    fn operator(&self) -> Option<Op> {
+        Some(match self.tokens.get(self.pos)?.as_str() {
+            "||" => Op::Or,
+            "&&" => Op::And,
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<"  => Op::Lt,
+            "<=" => Op::Le,
+            ">"  => Op::Gt,
+            ">=" => Op::Ge,
+            "+"  => Op::Add,
+            "-"  => Op::Sub,
+            "*"  => Op::Mul,
+            "/"  => Op::Div,
+            "%"  => Op::Rem,
+            _ => return None,
+        })
+    }
+
+    /// Operators whose precedence is at least `min`, applied from left to right
+    fn binary(&mut self, min: u8) -> Result<Expr, ExprError> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.operator().filter(|op| op.precedence() >= min) {
+            self.pos += 1;
+            let right = self.binary(op.precedence() + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ExprError> {
+        let token = self.next()?.to_string();
+        Ok(match token.as_str() {
+            "!" => Expr::Not(Box::new(self.unary()?)),
+            "-" => Expr::Neg(Box::new(self.unary()?)),
+            "(" => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                e
+            },
+            "mem" | "byte" | "io" => {
+                self.expect("[")?;
+                let e = Box::new(self.binary(0)?);
+                self.expect("]")?;
+                match token.as_str() {
+                    "mem" => Expr::Memory(e, true),
+                    "byte" => Expr::Memory(e, false),
+                    _ => Expr::Io(e),
+                }
+            },
+            "PC" | "pc" => Expr::Pc,
+            "hits" => Expr::Hits,
+            "new" => Expr::New,
+            name => {
+                if let Some(addr) = self.labels.data.get(name).or(self.labels.text.get(name)) {
+                    return Ok(Expr::Number(addr.0));
+                }
+                let register = |prefix| name.strip_prefix(prefix).and_then(|n| n.parse::<u8>().ok()).filter(|n| *n < 8);
+                if let Some(i) = register(['R', 'r']) { return Ok(Expr::Register(i)); }
+                if let Some(i) = register(['S', 's']) { return Ok(Expr::SystemRegister(i)); }
+                Expr::Number(parse_value(name).ok_or_else(|| ExprError::UnknownName(name.into()))?)
+            },
+        })
+    }
+}
+
+#[test]
+fn test_expressions() {
+    use crate::{read_instructions, Registers, Memory, ProgCounter};
+    use std::collections::HashMap;
+
+    let mut labels = Labels::default();
+    labels.data.insert("v".into(), MemAddr(0x4000));
+    let mut memory = Memory::new();
+    memory.insert_word(&MemAddr(0x4004), 12);
+    let mut cpu = Processador::new(Registers::default(), memory, ProgCounter(0), read_instructions("NOP").unwrap(),
+        HashMap::from([(MemAddr(1), crate::Value16Bit(3))]));
+    cpu.set_register(3, 0);
+    let context = Context { cpu: &cpu, hits: 6, value: Some(-2) };
+    let eval = |text: &str| Expression::parse(text, &labels).and_then(|e| e.eval(&context)).unwrap();
+
+    assert_eq!(eval("R3 == 0 && mem[v+4] > 10"), 1);
+    assert_eq!(eval("hits > 5"), 1);
+    assert_eq!(eval("io[1] != 0"), 1);
+    assert_eq!(eval("1 + 2 * 3 - 0x10"), -9);
+    assert_eq!(eval("(1 + 2) * 3 % 4"), 1);
+    assert_eq!(eval("byte[v + 5] || !R3"), 1);
+    assert_eq!(eval("-'a' + 1"), -96);
+    assert_eq!(eval("new * 2"), -4);
+    assert!(Expression::parse("mem[v", &labels).is_err());
+    assert!(Expression::parse("R3 ==", &labels).is_err());
+    assert!(Expression::parse("nowhere > 1", &labels).is_err());
+    assert!(Expression::parse("1 / R3", &labels).unwrap().eval(&context).is_err());
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufReader, Read, Write},
+    net::TcpListener,
+};
+
+use crate::{debugger::{Debugger, Stop}, execute::{MemAddr, ProgCounter}};
+
+/// Tells GDB which registers there are, since it doesn't know SISA: `r0`-`r7` and `pc`, 16 bits each
+pub const TARGET_XML: &str = include_str!("sisa-target.xml");
+
+/// Number of registers sent to GDB: the eight general purpose ones and the PC
+const REGISTERS: usize = 9;
+
+/// Largest packet GDB is told it can send or receive (in `qSupported`)
+const PACKET_SIZE: usize = 0x1000;
+
+/// Serves a [Debugger] to GDB through its remote serial protocol, so that any GDB front-end can
+/// read and write the registers and the memory, step, continue, and set breakpoints and
+/// watchpoints (`target remote localhost:PORT`).
+///
+/// The registers are sent in the order `r0`-`r7`, `pc`, as described by [TARGET_XML]. Memory that
+/// hasn't been written reads as 0.
+pub struct GdbStub<'a> {
+    debugger: &'a mut Debugger,
+    /// Breakpoints and watchpoints set by GDB (by its `Z` type and address), and their ids in the
+    /// debugger
+    points: HashMap<(u8, i16), Vec<usize>>,
+}
+
+impl<'a> GdbStub<'a> {
+    #[allow(missing_docs)]
+    pub fn new(debugger: &'a mut Debugger) -> Self {
+        Self { debugger, points: HashMap::new() }
+    }
+
+    /// Wait for GDB to connect to the given local port, and serve it until it detaches or kills
+    /// the program
+    pub fn serve(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for GDB on 127.0.0.1:{port} (target remote localhost:{port})");
+        let (stream, peer) = listener.accept()?;
+        println!("GDB connected from {peer}");
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        while let Some(packet) = read_packet(&mut reader)? {
+            writer.write_all(b"+")?;
+            let reply = self.handle(&packet);
+            writer.write_all(frame(&reply).as_bytes())?;
+            writer.flush()?;
+            if packet.starts_with(['D', 'k']) { break; }
+        }
+        Ok(())
+    }
+
+    /// Answer a single packet (without the `$` and the checksum). Unsupported packets get an empty
+    /// reply, as the protocol asks
+    pub fn handle(&mut self, packet: &str) -> String {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => "S05".into(),
+            "g" => (0..REGISTERS).map(|i| hex_word(self.register(i))).collect(),
+            "G" => {
+                let values: Vec<_> = (0..REGISTERS).filter_map(|i| args.get(i * 4..i * 4 + 4).and_then(parse_word)).collect();
+                if values.len() != REGISTERS { return "E01".into(); }
+                values.into_iter().enumerate().for_each(|(i, v)| self.set_register(i, v));
+                "OK".into()
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(i) if i < REGISTERS => hex_word(self.register(i)),
+                _ => "E01".into(),
+            },
+            "P" => {
+                let Some((i, value)) = args.split_once('=') else { return "E01".into() };
+                match (usize::from_str_radix(i, 16), parse_word(value)) {
+                    (Ok(i), Some(value)) if i < REGISTERS => { self.set_register(i, value); "OK".into() },
+                    _ => "E01".into(),
+                }
+            },
+            "m" => {
+                let Some((addr, len)) = parse_range(args) else { return "E01".into() };
+                let memory = self.debugger.cpu().memory();
+                // Each byte takes two characters of the reply, which has to fit in a packet
+                (0..len.min(PACKET_SIZE / 2)).map(|i| format!("{:02x}", memory.get_byte(&MemAddr(addr.wrapping_add(i as i16))).unwrap_or(0) as u8)).collect()
+            },
+            "M" => {
+                let Some((range, data)) = args.split_once(':') else { return "E01".into() };
+                let Some((addr, len)) = parse_range(range) else { return "E01".into() };
+                let Some(bytes) = (0..len).map(|i| data.get(i * 2..i * 2 + 2).and_then(|b| u8::from_str_radix(b, 16).ok())).collect::<Option<Vec<_>>>() else {
+                    return "E01".into();
+                };
+                let memory = self.debugger.cpu_mut().memory_mut();
+                bytes.into_iter().enumerate().for_each(|(i, b)| memory.insert_byte(&MemAddr(addr.wrapping_add(i as i16)), b as i8));
+                "OK".into()
+            },
+            "s" => {
+                if let Some(addr) = parse_hex(args) { self.debugger.cpu_mut().set_pc(ProgCounter(addr as u16)); }
+                let stop = self.debugger.step(1);
+                self.stop_reply(stop)
+            },
+            "c" => {
+                if let Some(addr) = parse_hex(args) { self.debugger.cpu_mut().set_pc(ProgCounter(addr as u16)); }
+                let stop = self.debugger.cont();
+                self.stop_reply(stop)
+            },
+            "Z" => self.insert_point(args).unwrap_or_else(|| "E01".into()),
+            "z" => self.remove_point(args).unwrap_or_else(|| "E01".into()),
+            "H" | "D" | "k" => "OK".into(),
+            "q" => self.query(packet),
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={PACKET_SIZE:x};qXfer:features:read+;swbreak+");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let Some((offset, len)) = range.split_once(',') else { return "E01".into() };
+            let (Ok(offset), Ok(len)) = (usize::from_str_radix(offset, 16), usize::from_str_radix(len, 16)) else { return "E01".into() };
+            let end = offset.saturating_add(len);
+            let chunk = TARGET_XML.get(offset.min(TARGET_XML.len())..end.min(TARGET_XML.len())).unwrap_or_default();
+            return format!("{}{chunk}", if end >= TARGET_XML.len() { 'l' } else { 'm' });
+        }
+        match packet {
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    /// `Z<type>,<addr>,<kind>`: type 0 (and 1) are breakpoints, 2 write watchpoints, 3 read
+    /// watchpoints and 4 access watchpoints
+    fn insert_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, len) = parse_point(args)?;
+        let range = format!("0x{:X}-0x{:X}", addr, addr.wrapping_add((len.max(1) - 1) as i16));
+        let ids = match kind {
+            0 | 1 => vec![self.debugger.add_breakpoint(&format!("0x{addr:X}")).ok()?],
+            2 => vec![self.debugger.add_watchpoint(&[&range, "write"]).ok()?],
+            3 => vec![self.debugger.add_watchpoint(&[&range, "read"]).ok()?],
+            4 => vec![self.debugger.add_watchpoint(&[&range, "read"]).ok()?, self.debugger.add_watchpoint(&[&range, "write"]).ok()?],
+            _ => return Some(String::new()),
+        };
+        self.points.entry((kind, addr)).or_default().extend(ids);
+        Some("OK".into())
+    }
+
+    fn remove_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, _) = parse_point(args)?;
+        for id in self.points.remove(&(kind, addr))? {
+            self.debugger.remove_breakpoint(id).ok()?;
+        }
+        Some("OK".into())
+    }
+
+    /// `W` with the exit code when the program halts, `T05` with the reason at a breakpoint or
+    /// watchpoint, `S02` (as if interrupted) after running too many instructions without stopping,
+    /// and `S05` otherwise
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Halted(halt) => format!("W{:02x}", halt.exit_code() as u8),
+            Stop::Breakpoint { .. } => "T05swbreak:;".into(),
+            Stop::Watchpoint { id, .. } => {
+                let Some(((kind, addr), _)) = self.points.iter().find(|(_, ids)| ids.contains(&id)) else { return "S05".into() };
+                let name = match kind { 2 => "watch", 3 => "rwatch", _ => "awatch" };
+                format!("T05{name}:{:x};", *addr as u16)
+            },
+            Stop::Limit => "S02".into(),
+            Stop::Done | Stop::Start => "S05".into(),
+        }
+    }
+
+    fn register(&self, i: usize) -> i16 {
+        match i {
+            8 => self.debugger.cpu().pc().0 as i16,
+            i => self.debugger.cpu().register(i as u8),
+        }
+    }
+
+    fn set_register(&mut self, i: usize, value: i16) {
+        match i {
+            8 => self.debugger.cpu_mut().set_pc(ProgCounter(value as u16)),
+            i => self.debugger.cpu_mut().set_register(i as u8, value),
+        }
+    }
+}
+
+/// Read the next packet, skipping acknowledgements and interrupts. Returns `None` once GDB is gone
+fn read_packet(reader: &mut impl Read) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 { return Ok(None); }
+        if byte[0] == b'$' { break; }
+    }
+    let mut packet = Vec::new();
+    loop {
+        if reader.read(&mut byte)? == 0 { return Ok(None); }
+        if byte[0] == b'#' { break; }
+        packet.push(byte[0]);
+    }
+    // The checksum is not checked, since TCP already does it
+    let mut checksum = [0; 2];
+    reader.read_exact(&mut checksum)?;
+    Ok(Some(String::from_utf8_lossy(&packet).into_owned()))
+}
+
+/// Wrap a reply as a packet: `$<data>#<checksum>`
+fn frame(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${data}#{checksum:02x}")
+}
+
+/// A word as GDB expects it: little endian hex
+fn hex_word(value: i16) -> String {
+    format!("{:02x}{:02x}", value as u8, (value >> 8) as u8)
+}
+
+fn parse_word(hex: &str) -> Option<i16> {
+    let low = u8::from_str_radix(hex.get(0..2)?, 16).ok()?;
+    let high = u8::from_str_radix(hex.get(2..4)?, 16).ok()?;
+    Some(((high as i16) << 8) | low as i16)
+}
+
+fn parse_hex(hex: &str) -> Option<i16> {
+    u16::from_str_radix(hex, 16).ok().map(|n| n as i16)
+}
+
+/// `<addr>,<len>`
+fn parse_range(args: &str) -> Option<(i16, usize)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)?, usize::from_str_radix(len, 16).ok()?))
+}
+
+/// `<type>,<addr>,<kind>`
+fn parse_point(args: &str) -> Option<(u8, i16, usize)> {
+    let (kind, range) = args.split_once(',')?;
+    let (addr, len) = parse_range(range)?;
+    Some((kind.parse().ok()?, addr, len))
+}
+
+#[test]
+fn test_gdb_packets() {
+    use crate::{read_instructions, Processador, Registers, Memory};
+
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("MOVI R1, 0x10\nADDI R2, R1, 1\nST 0x00(R1), R2\nNOP")?;
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    crate::set_quiet(true);
+    let mut gdb = GdbStub::new(&mut debugger);
+
+    assert_eq!(frame("OK"), "$OK#9a");
+    assert!(gdb.handle("qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+    assert_eq!(gdb.handle("s"), "S05");
+    assert_eq!(gdb.handle("p1"), "1000");
+    assert_eq!(gdb.handle("P2=3412"), "OK");
+    assert_eq!(gdb.handle("g"), ["0000", "1000", "3412", "0000", "0000", "0000", "0000", "0000", "0200"].concat());
+    assert_eq!(gdb.handle("M20,2:abcd"), "OK");
+    assert_eq!(gdb.handle("m20,3"), "abcd00");
+    assert_eq!(gdb.handle("m0,ffffffff").len(), PACKET_SIZE);
+    assert!(gdb.handle(&format!("qXfer:features:read:target.xml:1,{:x}", usize::MAX)).starts_with('l'));
+    assert_eq!(gdb.handle("Z0,4,2"), "OK");
+    assert_eq!(gdb.handle("Z2,10,2"), "OK");
+    assert_eq!(gdb.handle("c"), "T05swbreak:;");
+    assert_eq!(gdb.handle("z0,4,2"), "OK");
+    assert_eq!(gdb.handle("c"), "T05watch:10;");
+    assert_eq!(gdb.handle("c"), "W00");
+
+    // A program that runs for too long is interrupted
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("ADDI R1, R1, 1\nBZ R0, -2")?;
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    let mut gdb = GdbStub::new(&mut debugger);
+    assert!(gdb.handle("qXfer:features:read:target.xml:0,1000").contains("<architecture>msp430</architecture>"));
+    assert_eq!(gdb.handle("c"), "S02");
+}
@@ -1,8 +1,10 @@
-use std::{collections::HashMap, path::Path, fs::File, io::Read};
+use std::{collections::{HashMap, VecDeque}, path::Path, fs::File, io::Read};
 
+use anyhow::Context;
 use boml::prelude::*;
 
-use crate::{execute::{Reg, RegLabel, Registers, Value16Bit, MemAddr}, print_info, norm_n, PreparationError, spec::Instruction, Instructions, Memory};
+use crate::{execute::{Reg, RegLabel, Registers, Value16Bit, MemAddr}, print_info, norm_n, parse_value, PreparationError, spec::Instruction, Instructions, Memory};
+use crate::devices::{IoTimeline, Trigger, BoardPorts};
 
 /// Describes all variants of filesystem errors, for using in [ExecutionError]
 #[derive(Debug, thiserror::Error)]
@@ -20,6 +22,16 @@ pub enum FileError {
     UnparsableIO,
     #[error("registers' file is not properly written")]
     UnparsableRegister,
+    #[error("project file is not properly written")]
+    UnparsableProject,
+    #[error("keys' file is not properly written")]
+    UnparsableKeys,
+    #[error("expected output file is not properly written")]
+    UnparsableOutput,
+    #[error("board script is not properly written")]
+    UnparsableBoard,
+    #[error("trace file is not properly written")]
+    UnparsableTrace,
 }
 
 impl From<FileError> for PreparationError {
@@ -82,6 +94,149 @@ pub fn read_io_once(filename: &impl AsRef<Path>) -> anyhow::Result<HashMap<MemAd
     Ok(io)
 }
 
+/// Read an IO timeline from file, in the TOML format. It's a superset of the format of
+/// [read_io_once]: the top level values are the starting values of the ports, and on top of that
+/// there may be:
+/// - A `[queue]` table, with a list of values per port. Each read of the port consumes one
+/// - A `[[change]]` list, where each change has the `port`, the new `value` and when it happens:
+///   at a given `step` or `cycle`, or `after_reads` reads of the port
+///
+/// For example:
+/// ```txt
+/// 1 = "0x0000"
+///
+/// [queue]
+/// 0 = ["5", "0x0081"]
+///
+/// [[change]]
+/// port = 1
+/// step = 20
+/// value = "0x0001"
+/// ```
+/// Represents `KEY-STATUS` being pressed after 20 instructions, with `KEY-DATA` giving 5 and then
+/// `0x81`
+pub fn read_io_timeline(filename: &impl AsRef<Path>) -> anyhow::Result<IoTimeline> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+
+    let table: Toml = boml::parse(&contents).or(Err(FileError::UnparsableIO))?;
+    let parse = |v: &str| norm_n(v).map(|n| n as i16).or(Err(FileError::UnparsableIO));
+
+    let mut timeline = IoTimeline::new();
+    for (m, v) in table.iter() {
+        match (m.as_str(), v.ty()) {
+            ("queue", TomlValueType::Table) => {
+                for (port, values) in v.as_table().unwrap().iter() {
+                    let values = values.as_array().ok_or(FileError::UnparsableIO)?;
+                    for value in values {
+                        let value = value.as_string().ok_or(FileError::UnparsableIO)?;
+                        timeline.enqueue(MemAddr(parse(port.as_str())?), Value16Bit(parse(value)?));
+                    }
+                }
+            },
+            ("change", TomlValueType::Array) => {
+                for change in v.as_array().unwrap() {
+                    let change = change.as_table().ok_or(FileError::UnparsableIO)?;
+                    let port = match change.get_integer("port") {
+                        Ok(port) => port as i16,
+                        Err(_) => parse(change.get_string("port").or(Err(FileError::UnparsableIO))?)?,
+                    };
+                    let value = parse(change.get_string("value").or(Err(FileError::UnparsableIO))?)?;
+                    let trigger = read_trigger(change).ok_or(FileError::UnparsableIO)?;
+                    timeline.schedule(MemAddr(port), trigger, Value16Bit(value));
+                }
+            },
+            _ => {
+                let v = v.as_string().ok_or(FileError::UnparsableIO)?;
+                timeline.set(MemAddr(parse(m.as_str())?), Value16Bit(parse(v)?));
+            },
+        }
+    }
+    Ok(timeline)
+}
+
+/// When a scripted change happens: at a given `step` or `cycle`, or `after_reads` reads
+fn read_trigger(change: &TomlTable) -> Option<Trigger> {
+    if let Ok(step) = change.get_integer("step") { Some(Trigger::Step(step as usize)) }
+    else if let Ok(cycle) = change.get_integer("cycle") { Some(Trigger::Cycle(cycle as usize)) }
+    else if let Ok(reads) = change.get_integer("after_reads") { Some(Trigger::Reads(reads as usize)) }
+    else { None }
+}
+
+/// Read the state of the switches and pushbuttons of the [Board](crate::devices::Board) over
+/// time, in the TOML format. `switches` and `keys` are their starting state (bit `i` is `SW i` or
+/// `KEY i`), and each `[[change]]` changes one of them (`input`) to a new `value` at a given `step`
+/// or `cycle` (not `after_reads`, since the board is read for more than the program's `IN`s).
+///
+/// For example:
+/// ```txt
+/// switches = "0x0003"
+/// keys = "0x0000"
+///
+/// [[change]]
+/// input = "keys"
+/// step = 100
+/// value = "0x0001"
+/// ```
+/// Represents `SW0` and `SW1` being up from the start, and `KEY0` being pressed after 100
+/// instructions
+pub fn read_board_script(filename: &impl AsRef<Path>, ports: &BoardPorts) -> anyhow::Result<IoTimeline> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+
+    let table: Toml = boml::parse(&contents).or(Err(FileError::UnparsableBoard))?;
+    let parse = |v: &str| norm_n(v).map(|n| n as i16).or(Err(FileError::UnparsableBoard));
+    let port = |input: &str| match input {
+        "switches" => Ok(ports.switches),
+        "keys"     => Ok(ports.keys),
+        _          => Err(FileError::UnparsableBoard),
+    };
+
+    let mut timeline = IoTimeline::new();
+    timeline.set(MemAddr(ports.switches), Value16Bit(0));
+    timeline.set(MemAddr(ports.keys), Value16Bit(0));
+    for (m, v) in table.iter() {
+        if m.as_str() == "change" {
+            for change in v.as_array().ok_or(FileError::UnparsableBoard)? {
+                let change = change.as_table().ok_or(FileError::UnparsableBoard)?;
+                let input = port(change.get_string("input").or(Err(FileError::UnparsableBoard))?)?;
+                let value = parse(change.get_string("value").or(Err(FileError::UnparsableBoard))?)?;
+                let trigger = read_trigger(change).ok_or(FileError::UnparsableBoard)?;
+                if let Trigger::Reads(_) = trigger {
+                    return Err(FileError::UnparsableBoard).context("the changes of the board happen at a step or cycle, not after_reads");
+                }
+                timeline.schedule(MemAddr(input), trigger, Value16Bit(value));
+            }
+        } else {
+            let v = v.as_string().ok_or(FileError::UnparsableBoard)?;
+            timeline.set(MemAddr(port(m.as_str())?), Value16Bit(parse(v)?));
+        }
+    }
+    Ok(timeline)
+}
+
+/// Read the keys that will be pressed on a [Keyboard](crate::devices::Keyboard), one per line.
+/// Each one may be a decimal or hex number, or a character between single quotes.
+///
+/// For example:
+/// ```txt
+/// 5
+/// 0x0081
+/// 'a'
+/// ```
+pub fn read_keys(filename: &impl AsRef<Path>) -> anyhow::Result<VecDeque<Value16Bit>> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+
+    contents.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| parse_value(l).map(Value16Bit).ok_or(FileError::UnparsableKeys.into()))
+        .collect()
+}
+
 /// Read memory list from file, in the TOML format. They must be separated by newlines. 
 ///
 /// For example:
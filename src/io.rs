@@ -0,0 +1,244 @@
+use std::{collections::HashMap, hash::{Hash, Hasher}, ops::RangeInclusive, str::FromStr};
+
+use crate::{execute::{MemAddr, Value16Bit}, devices::Trigger, norm_n};
+
+/// A point in time of the simulation, passed to [IoDevice::tick]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Clock {
+    /// Number of instructions executed so far
+    pub step: usize,
+    /// Number of cycles elapsed so far (see [NumInstruccions](crate::NumInstruccions))
+    pub cycle: usize,
+}
+
+/// Anything that can be attached to the [IOSystem] to be accessed through `IN` and `OUT`:
+/// keyboards, displays, scripted inputs, test doubles...
+pub trait IoDevice {
+    /// Value that `IN` gets when reading from `port`, or `None` if the device has nothing there. An
+    /// error stops the simulation
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError>;
+    /// Called by `OUT` when writing `value` to `port`
+    fn write(&mut self, port: &MemAddr, value: Value16Bit);
+    /// Called after every instruction, with the time that has passed since the start
+    fn tick(&mut self, _clock: &Clock) {}
+    /// Hash whatever part of the device's state affects what it will return from now on. Used to
+    /// tell if the program is stuck in a loop, so a device whose reads may change in the future
+    /// must include that in the hash. Changes that happen as time passes are told apart with
+    /// [scheduled_changes](IoDevice::scheduled_changes) instead, so the time itself is left out
+    fn hash_state(&self, _hasher: &mut dyn Hasher) {}
+    /// Changes that will happen by themselves once a step or cycle is reached
+    /// ([Trigger::Step] or [Trigger::Cycle]), and the port they happen to. A program that goes
+    /// around the same loop reading one of those ports is waiting for the change, so it may skip
+    /// ahead to it
+    fn scheduled_changes(&self) -> Vec<(MemAddr, Trigger)> { Vec::new() }
+    /// What the device's ports hold right now, for debuggers to show. Reading a port may change
+    /// it, so this must give the values without doing so (leaving out the ports that can't)
+    fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> { Vec::new() }
+}
+
+/// Errors using the IO bus, which stop the simulation
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum IoError {
+    #[error("no device has a value for port {0}")]
+    NoValue(MemAddr),
+    #[error("could not get a value for port {port}: {reason}")]
+    NoInput { port: MemAddr, reason: String },
+    #[error("port {port} was used by an instruction that was undone and then changed, so the devices can't be used again until step {step} (start over to use them)")]
+    Undone { port: MemAddr, step: usize },
+}
+
+/// The IO bus of the [Processador](crate::Processador). Devices are attached to ranges of ports,
+/// and `IN`/`OUT` are dispatched to the ones whose range contains the port. When more than one
+/// device can be read from a port, the one attached last wins. If none of them has a value, the
+/// fixed values of the ports (the IO file's) are used, and then the fallback device (if any) is
+/// asked.
+#[derive(Default)]
+pub struct IOSystem {
+    devices: Vec<(RangeInclusive<i16>, Box<dyn IoDevice>)>,
+    ports: StaticPorts,
+    fallback: Option<Box<dyn IoDevice>>,
+}
+
+impl IOSystem {
+    /// Create a bus with no devices
+    pub fn new() -> Self { Self::default() }
+
+    /// Create a bus whose ports hold fixed values, like the ones read from an IO file
+    pub fn from_ports(ports: HashMap<MemAddr, Value16Bit>) -> Self {
+        Self { ports: StaticPorts(ports), ..Self::default() }
+    }
+
+    /// Replace the fixed values of the ports, leaving the devices as they are
+    pub fn set_ports(&mut self, ports: HashMap<MemAddr, Value16Bit>) {
+        self.ports = StaticPorts(ports);
+    }
+
+    /// Attach a device to the given (inclusive) range of ports
+    pub fn attach(&mut self, ports: RangeInclusive<i16>, device: Box<dyn IoDevice>) {
+        self.devices.push((ports, device));
+    }
+
+    /// Set the device that is read from when no other device has a value for the port
+    pub fn set_fallback(&mut self, device: Option<Box<dyn IoDevice>>) {
+        self.fallback = device;
+    }
+
+    /// Read from a port, failing if no device has a value there
+    pub fn read(&mut self, port: &MemAddr) -> Result<Value16Bit, IoError> {
+        for (_, device) in self.devices.iter_mut().rev().filter(|(ports, _)| ports.contains(&port.0)) {
+            if let Some(value) = device.read(port)? { return Ok(value); }
+        }
+        if let Some(value) = self.ports.read(port)? { return Ok(value); }
+        match &mut self.fallback {
+            Some(fallback) => fallback.read(port)?.ok_or(IoError::NoValue(port.clone())),
+            None => Err(IoError::NoValue(port.clone())),
+        }
+    }
+
+    /// Write to a port. Every device attached to it gets the value
+    pub fn write(&mut self, port: &MemAddr, value: Value16Bit) {
+        self.devices.iter_mut()
+            .filter(|(ports, _)| ports.contains(&port.0))
+            .for_each(|(_, device)| device.write(port, value.clone()));
+    }
+
+    /// Let every device know that time has passed
+    pub fn tick(&mut self, clock: &Clock) {
+        self.devices.iter_mut().for_each(|(_, device)| device.tick(clock));
+        if let Some(fallback) = &mut self.fallback { fallback.tick(clock); }
+    }
+
+    /// Every change that the devices have scheduled (see [IoDevice::scheduled_changes])
+    pub fn scheduled_changes(&self) -> Vec<(MemAddr, Trigger)> {
+        self.devices.iter().map(|(_, device)| device).chain(&self.fallback)
+            .flat_map(|device| device.scheduled_changes())
+            .collect()
+    }
+
+    /// What a port holds right now, without reading it (see [IoDevice::port_values]). `None` if
+    /// its device can't tell
+    pub fn peek(&self, port: &MemAddr) -> Option<Value16Bit> {
+        let find = |values: Vec<(MemAddr, Value16Bit)>| values.into_iter().find(|(p, _)| p == port).map(|(_, v)| v);
+        self.devices.iter().rev()
+            .filter(|(ports, _)| ports.contains(&port.0))
+            .find_map(|(_, device)| find(device.port_values()))
+            .or_else(|| find(self.ports.port_values()))
+            .or_else(|| find(self.fallback.as_ref()?.port_values()))
+    }
+
+    /// What every port holds right now (see [IoDevice::port_values]), sorted by port. When more
+    /// than one device has a value for the port, the one that would be read is given
+    pub fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> {
+        let mut values: HashMap<MemAddr, Value16Bit> = HashMap::new();
+        values.extend(self.fallback.iter().flat_map(|device| device.port_values()));
+        values.extend(self.ports.port_values());
+        for (_, device) in &self.devices {
+            values.extend(device.port_values());
+        }
+        let mut values: Vec<_> = values.into_iter().collect();
+        values.sort_by_key(|(port, _)| port.0);
+        values
+    }
+
+    /// Hash the state of every device (see [IoDevice::hash_state])
+    pub fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        self.ports.hash_state(hasher);
+        for (ports, device) in &self.devices {
+            ports.hash(&mut hasher);
+            device.hash_state(hasher);
+        }
+        if let Some(fallback) = &self.fallback { fallback.hash_state(hasher); }
+    }
+}
+
+/// A region of the memory that is claimed by the IO bus: `LD`/`LDB`/`ST`/`STB` there go to the
+/// devices attached to the bus instead of the memory. Every port takes up a word, so `start` is
+/// `first_port`, `start + 2` is `first_port + 1` and so on. `LDB`/`STB` read or write one of the
+/// port's bytes: `STB` keeps the other one as the port holds it (or 0, if its device can't tell
+/// without being read, see [IoDevice::port_values]).
+///
+/// Addresses are unsigned, so a region may go across `0x7FFF`-`0x8000`.
+///
+/// Written as `START-END=PORT` (e.g. `0xFF00-0xFF0F=0`) in the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmioRegion {
+    /// Addresses claimed
+    pub addrs: RangeInclusive<u16>,
+    /// Port that the first address is mapped to
+    pub first_port: i16,
+}
+
+impl MmioRegion {
+    /// The region of the addresses `addrs`, starting at `first_port`. `None` if it ends before it
+    /// starts, since it would claim nothing
+    pub fn new(addrs: RangeInclusive<u16>, first_port: i16) -> Option<Self> {
+        (addrs.start() <= addrs.end()).then_some(Self { addrs, first_port })
+    }
+
+    /// Port (and whether it's its high byte) that an address is mapped to, if it's in the region
+    pub fn port(&self, addr: &MemAddr) -> Option<(MemAddr, bool)> {
+        let addr = addr.0 as u16;
+        if !self.addrs.contains(&addr) { return None; }
+        let offset = addr - self.addrs.start();
+        Some((MemAddr(self.first_port.wrapping_add((offset / 2) as i16)), offset % 2 == 1))
+    }
+}
+
+impl FromStr for MmioRegion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("could not understand the memory-mapped IO region '{s}' (expected START-END=PORT)");
+        let (addrs, port) = s.split_once('=').ok_or_else(error)?;
+        let (start, end) = addrs.split_once('-').ok_or_else(error)?;
+        let n = |v: &str| norm_n(v.trim()).map_err(|_| error());
+        Self::new(n(start)?..=n(end)?, n(port)? as i16)
+            .ok_or_else(|| format!("the memory-mapped IO region '{s}' ends before it starts"))
+    }
+}
+
+/// Ports that always hold the same value: the IO file's model. Writing to them does nothing
+#[derive(Default)]
+pub struct StaticPorts(pub HashMap<MemAddr, Value16Bit>);
+
+impl IoDevice for StaticPorts {
+    fn read(&mut self, port: &MemAddr) -> Result<Option<Value16Bit>, IoError> { Ok(self.0.get(port).cloned()) }
+    fn write(&mut self, _port: &MemAddr, _value: Value16Bit) {}
+    fn port_values(&self) -> Vec<(MemAddr, Value16Bit)> {
+        self.0.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+    fn hash_state(&self, mut hasher: &mut dyn Hasher) {
+        let mut ports: Vec<(i16, i16)> = self.0.iter().map(|(k, v)| (k.0, v.0)).collect();
+        ports.sort_unstable();
+        ports.hash(&mut hasher);
+    }
+}
+
+#[test]
+fn test_mmio_region() {
+    let region: MmioRegion = "0xFF00-0xFF0F=4".parse().unwrap();
+    assert_eq!(region.port(&MemAddr(0xFF00u16 as i16)), Some((MemAddr(4), false)));
+    assert_eq!(region.port(&MemAddr(0xFF03u16 as i16)), Some((MemAddr(5), true)));
+    assert_eq!(region.port(&MemAddr(0xFF10u16 as i16)), None);
+    assert!("0xFF00=4".parse::<MmioRegion>().is_err());
+    assert!("0xFF0F-0xFF00=0".parse::<MmioRegion>().unwrap_err().contains("ends before it starts"));
+    assert!("0xFF00-0xFF00=0".parse::<MmioRegion>().is_ok());
+
+    let region: MmioRegion = "0x7FFE-0x8001=4".parse().unwrap();
+    assert_eq!(region.port(&MemAddr(0x7FFF)), Some((MemAddr(4), true)));
+    assert_eq!(region.port(&MemAddr(0x8000u16 as i16)), Some((MemAddr(5), false)));
+    assert_eq!(region.port(&MemAddr(0x8002u16 as i16)), None);
+}
+
+#[test]
+fn test_static_ports() {
+    let mut io = IOSystem::from_ports(HashMap::from([(MemAddr(1), Value16Bit(1)), (MemAddr(2), Value16Bit(2))]));
+    io.attach(2..=2, Box::new(StaticPorts(HashMap::from([(MemAddr(2), Value16Bit(20))]))));
+    for value in 3..6 {
+        io.set_ports(HashMap::from([(MemAddr(1), Value16Bit(value))]));
+    }
+    assert_eq!(io.read(&MemAddr(1)).unwrap().0, 5);
+    assert_eq!(io.read(&MemAddr(2)).unwrap().0, 20);
+    assert!(matches!(io.read(&MemAddr(3)), Err(IoError::NoValue(MemAddr(3)))));
+    assert_eq!(io.devices.len(), 1);
+}
@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use crate::{execute::{Effects, NumInstruccions, ProgCounter}, output::OutputRecord, tlb::Mmu};
+
+/// Everything that executing one instruction changed, so that it can be undone (and then redone)
+#[derive(Debug, Clone)]
+pub(crate) struct JournalEntry {
+    /// The PC before and after
+    pub(crate) pc: (ProgCounter, ProgCounter),
+    /// The instruction count before and after (which may jump ahead when skipping a polling loop)
+    pub(crate) count: (NumInstruccions, NumInstruccions),
+    /// General purpose registers that changed, with their old and new values
+    pub(crate) registers: Vec<(u8, i16, i16)>,
+    /// System registers that changed, with their old and new values
+    pub(crate) system_registers: Vec<(u8, i16, i16)>,
+    /// The MMU before and after, if the instruction changed its TLBs
+    pub(crate) mmu: Option<(Mmu, Mmu)>,
+    /// Values written with `OUT`
+    pub(crate) outputs: Vec<OutputRecord>,
+    /// What the instruction read and wrote (including the memory, with its old values)
+    pub(crate) effects: Effects,
+}
+
+/// The undo journal of a [Processador](crate::Processador): the entries of the instructions
+/// executed so far, and the ones that have been undone (to redo them without asking the IO devices
+/// again).
+///
+/// Only the last [CAPACITY](Journal::CAPACITY) instructions can be undone. The IO devices can't go
+/// back in time, so once the instructions undone are forgotten, the ones that used them can't be
+/// executed again (see [devices_ahead](Journal::devices_ahead)).
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    done: VecDeque<JournalEntry>,
+    undone: Vec<JournalEntry>,
+    devices_ahead: usize,
+}
+
+impl Journal {
+    /// Maximum number of instructions that are remembered
+    pub(crate) const CAPACITY: usize = 100_000;
+
+    /// Remember a newly executed instruction. Whatever had been undone can't be redone anymore
+    pub(crate) fn push(&mut self, entry: JournalEntry) {
+        self.forget_undone();
+        self.record(entry);
+    }
+
+    fn record(&mut self, entry: JournalEntry) {
+        if self.done.len() == Self::CAPACITY { self.done.pop_front(); }
+        self.done.push_back(entry);
+    }
+
+    /// Take the last instruction executed, to undo it
+    pub(crate) fn undo(&mut self) -> Option<JournalEntry> {
+        let entry = self.done.pop_back()?;
+        self.undone.push(entry.clone());
+        Some(entry)
+    }
+
+    /// Take the last instruction undone, to redo it
+    pub(crate) fn redo(&mut self) -> Option<JournalEntry> {
+        let entry = self.undone.pop()?;
+        self.record(entry.clone());
+        Some(entry)
+    }
+
+    /// The last instruction executed
+    pub(crate) fn last(&self) -> Option<&JournalEntry> { self.done.back() }
+
+    /// Forget the instructions that have been undone, since the state has been changed by hand and
+    /// they would no longer follow from it
+    pub(crate) fn forget_undone(&mut self) {
+        let last_io = self.undone.iter()
+            .filter(|e| !e.effects.io_reads.is_empty() || !e.effects.io_writes.is_empty())
+            .map(|e| e.count.0.total() + 1)
+            .max();
+        self.devices_ahead = self.devices_ahead.max(last_io.unwrap_or(0));
+        self.undone.clear();
+    }
+
+    /// Number of instructions executed (counting the one that did it) by the last IO access that
+    /// was undone and then forgotten. The devices have already seen it, so they can't be used
+    /// until the program gets past it again
+    pub(crate) fn devices_ahead(&self) -> usize { self.devices_ahead }
+}
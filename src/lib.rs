@@ -3,7 +3,7 @@
 
 #![doc = include_str!("../README.md")]
 
-use std::{num::ParseIntError, fmt::Display, collections::HashMap};
+use std::{num::ParseIntError, fmt::Display, collections::HashMap, sync::atomic::{AtomicBool, Ordering}};
 
 type Instructions = HashMap<MemAddr, Instruction>;
 
@@ -12,18 +12,49 @@ mod execute;
 mod parsing;
 mod spec;
 mod input;
+mod exception;
+mod tlb;
+mod profile;
+mod project;
+mod loop_detection;
+mod io;
+mod output;
+mod debugger;
+mod watchpoint;
+mod journal;
+mod gdb;
+mod dap;
+mod expression;
+mod source_map;
+mod trace;
 #[cfg(feature = "executable")] mod cli;
+#[cfg(feature = "executable")] mod tui;
 /// Holds all functions and types relating to the process from when the file is read from disk
 /// until when it is turned into proper instructions. It deals with labels, functions like `lo()`
 /// and `hi()`, keeping track of the address of each value, setting up the .data values properly in
 /// their appropriate positions in memory and the like.
 pub mod preprocessor;
+/// Ready made [IoDevice]s that can be attached to the [IOSystem]
+pub mod devices;
 
 pub use input::*;
-pub use execute::{Memory, IOSystem, Registers, ProgCounter, Processador};
+pub use execute::{Memory, MemoryHook, Effects, Registers, ProgCounter, MemAddr, Value16Bit, Processador, Halt, Fault, NumInstruccions, Mode, PSW_SYSTEM_MODE, PSW_INTERRUPTS_ENABLED};
 pub use spec::Instruction;
-#[cfg(feature = "executable")] pub use cli::CliArgs;
-use spec::execute::MemAddr;
+pub use exception::{Exception, Access};
+pub use tlb::{Mmu, Tlb, TlbEntry, DEFAULT_TLB_ENTRIES};
+pub use profile::{IsaProfile, ProfileError};
+pub use project::{Project, read_project};
+pub use io::{IoDevice, IoError, IOSystem, Clock, StaticPorts, MmioRegion};
+pub use output::{OutputRecord, OutputMismatch, output_to_csv, output_to_json, read_expected_output, compare_output};
+pub use debugger::{Debugger, DebugError, Stop, Breakpoint};
+pub use gdb::{GdbStub, TARGET_XML};
+pub use dap::{DapServer, Launcher};
+pub use watchpoint::{Watchpoint, WatchTarget, WatchKind};
+pub use expression::{Expression, ExprError, Context};
+pub use source_map::{SourceMap, SourceLocation};
+pub use trace::{TraceRecord, TraceDivergence, read_trace, compare_traces};
+#[cfg(feature = "executable")] pub use cli::{CliArgs, Command};
+#[cfg(feature = "executable")] pub use tui::Tui;
 
 /// Main error enum for execution. Mostly seen at the start of execution.
 #[derive(Debug)]
@@ -53,16 +84,38 @@ impl std::error::Error for PreparationError {}
 pub(crate) fn norm_n(input: &str) -> Result<u16, ParseIntError> {
     let input = input.replace(',', "");
     if input.len() <= 2 || &input[..2] != "0x" { // Is dec here
-        if let Some(val) = input.strip_prefix('-') {
-            Ok(-((val.parse::<u16>()?) as i16) as u16)
-        } else { input.parse() }
+        // Anything from i16::MIN to u16::MAX, so negative numbers have to fit in an i16
+        if input.starts_with('-') { input.parse::<i16>().map(|val| val as u16) }
+        else { input.parse() }
     } else {
         u16::from_str_radix(&input[2..], 16)
     }
 }
 
+/// Parse a value typed in by the user: a decimal or hex number (see [norm_n]) or a character
+/// between single quotes, like `'a'`, which becomes its ASCII code
+pub(crate) fn parse_value(input: &str) -> Option<i16> {
+    let input = input.trim();
+    match input.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+        Some(c) if c.chars().count() == 1 => u16::try_from(c.chars().next()? as u32).ok().map(|c| c as i16),
+        _ => norm_n(input).ok().map(|n| n as i16),
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Stop (or start again) printing how the simulation goes: the [print_info] messages, the
+/// instructions being run and the values written with `OUT`. Meant for frontends like the
+/// [Debugger], which show the state by themselves
+pub fn set_quiet(quiet: bool) { QUIET.store(quiet, Ordering::Relaxed); }
+
+/// Whether [set_quiet] is on
+pub(crate) fn is_quiet() -> bool { QUIET.load(Ordering::Relaxed) }
+
 /// Print information in a darker, less noticeable color prefixed by `[INFO]:`
-pub fn print_info(info: &str) { println!("[INFO]: \x1b[37m{}\x1b[0m",info); }
+pub fn print_info(info: &str) {
+    if !is_quiet() { println!("[INFO]: \x1b[37m{}\x1b[0m",info); }
+}
 
 #[test]
 fn test_norm() {
@@ -86,4 +139,16 @@ fn test_norm() {
     }
 }
 
-
+#[test]
+fn test_parse_value() {
+    assert_eq!(parse_value("12\n"), Some(12));
+    assert_eq!(parse_value("0x0F"), Some(15));
+    assert_eq!(parse_value("-1"), Some(-1));
+    assert_eq!(parse_value("-32768"), Some(i16::MIN));
+    assert_eq!(parse_value("65535"), Some(-1));
+    assert_eq!(parse_value("-32769"), None);
+    assert_eq!(parse_value("-40000"), None);
+    assert_eq!(parse_value("65536"), None);
+    assert_eq!(parse_value("'a'"), Some(97));
+    assert_eq!(parse_value("a"), None);
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use crate::NumInstruccions;
+
+/// Remembers the states the processor has gone through (by their hash) to notice when one repeats.
+/// Since the simulation is deterministic, going through the same state twice means that the program
+/// will keep on going around that cycle forever.
+///
+/// States are only recorded when jumping backwards, since that's the only way of getting back to a
+/// previous state, which keeps this cheap (the memory keeps its own hash up to date, see
+/// [Memory::digest](crate::Memory::digest)). At most [CAPACITY](LoopDetector::CAPACITY) states are
+/// remembered: then they're forgotten, and a cycle is noticed once it goes around twice more. The
+/// [Processador](crate::Processador) also forgets them when it steps back.
+///
+/// The time that has passed isn't part of the state, so a program that is waiting for a scheduled
+/// IO change also goes through the same state twice: it's the caller's job to tell them apart.
+///
+/// Only the hashes are kept, not the states, so two different states with the same hash would be
+/// taken for a loop and halt a program that would have kept going. With `n` states recorded, the
+/// odds of that are about `n² / 2⁶⁵`: less than one in 10¹¹ within the 10000 instructions a run is
+/// limited to, which isn't worth keeping a copy of the memory around at every backward jump.
+#[derive(Debug, Default)]
+pub(crate) struct LoopDetector {
+    seen: HashMap<u64, LoopPoint>,
+}
+
+/// When a state was reached
+#[derive(Debug, Clone)]
+pub(crate) struct LoopPoint {
+    /// Instructions executed until then
+    pub(crate) count: NumInstruccions,
+    /// Values that had been written with `OUT` until then
+    pub(crate) outputs: usize,
+}
+
+impl LoopDetector {
+    /// Maximum number of states that are remembered
+    pub(crate) const CAPACITY: usize = 100_000;
+
+    /// Record the state with the given hash, reached at `point`. If it had already been reached,
+    /// when that happened is returned
+    pub(crate) fn check(&mut self, state_hash: u64, point: LoopPoint) -> Option<LoopPoint> {
+        if self.seen.len() >= Self::CAPACITY && !self.seen.contains_key(&state_hash) { self.seen.clear(); }
+        self.seen.insert(state_hash, point)
+    }
+}
+
+#[test]
+fn test_detects_non_halting() {
+    use crate::{read_instructions, Processador, Registers, Memory, ProgCounter, Halt};
+    use std::collections::HashMap;
+
+    let instructions = read_instructions("MOVI R0, 0x1\nBNZ R0, -2").unwrap();
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new());
+    let halt = (0..10).find_map(|_| cpu.execute_next(false).err());
+    assert!(matches!(halt, Some(Halt::NonTerminatingLoop { length: 2, .. })));
+    assert_ne!(halt.unwrap().exit_code(), 0);
+}
+
+#[test]
+fn test_fast_forwards_polling() {
+    use crate::{read_instructions, Processador, Registers, Memory, ProgCounter, MemAddr, Value16Bit, devices::{IoTimeline, Trigger}};
+    use std::collections::HashMap;
+
+    // Wait for port 0 to become 1, which happens after 5000 steps
+    let instructions = read_instructions("IN R0, 0\nBZ R0, -2").unwrap();
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new());
+    let mut timeline = IoTimeline::new();
+    timeline.set(MemAddr(0), Value16Bit(0));
+    timeline.schedule(MemAddr(0), Trigger::Step(5000), Value16Bit(1));
+    cpu.attach_device(0..=0, Box::new(timeline));
+
+    let executed = (0..100).take_while(|_| cpu.execute_next(false).is_ok()).count();
+    assert!(executed < 100);
+    assert!((5000..5004).contains(&cpu.instruction_count().total()));
+}
+
+#[test]
+fn test_ignores_changes_not_read() {
+    use crate::{read_instructions, Processador, Registers, Memory, ProgCounter, MemAddr, Value16Bit, Halt, devices::{IoTimeline, Trigger}};
+    use std::collections::HashMap;
+
+    // Port 0 changes after 5000 steps, but the loop never looks at it
+    let instructions = read_instructions("IN R0, 0\nMOVI R1, 1\nBNZ R1, -2").unwrap();
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new());
+    let mut timeline = IoTimeline::new();
+    timeline.set(MemAddr(0), Value16Bit(0));
+    timeline.schedule(MemAddr(0), Trigger::Step(5000), Value16Bit(1));
+    cpu.attach_device(0..=0, Box::new(timeline));
+
+    let halt = (0..100).find_map(|_| cpu.execute_next(false).err());
+    assert!(matches!(halt, Some(Halt::NonTerminatingLoop { length: 2, .. })));
+}
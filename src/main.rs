@@ -7,64 +7,324 @@ fn main() {
 }
 
 #[cfg(feature = "executable")]
-use std::{collections::HashMap, convert::Infallible};
+use std::{cell::RefCell, collections::HashMap, convert::Infallible, fs::File, io::{BufWriter, Write}, rc::Rc};
 use clap::Parser;
-use sICmulador::{*, preprocessor::{Input, parse_complete_file}};
+use sICmulador::{*, preprocessor::{Input, Labels, parse_complete_file}, devices::{Keyboard, KeySource, StdinPrompt, Board, BoardPorts, IoTimeline, Console, Framebuffer, FrameDumper, Rng}};
 pub use sICmulador::CliArgs;
 
 
 #[cfg(feature = "executable")]
 fn main() -> anyhow::Result<Infallible> {
     let args = CliArgs::parse();
+    if let Some(Command::TraceDiff { got, expected }) = &args.command { trace_diff(got, expected) }
+    if args.dap { dap() }
+    if args.debug || args.tui || !args.breakpoints.is_empty() || args.gdb.is_some() { debug(args) }
 
-    let io_system = match args.io_file {
-        Some(f) => read_io_once(&f)?,
+    let Session { mut cpu, outputs, source_map, .. } = setup(&args)?;
+    print_info("\n\nStarting with state:");
+    println!("{cpu}");
+
+    let mut instructions_executed = 0;
+    let mut trace = match &args.trace {
+        Some(f) => Some(BufWriter::new(File::create(f)?)),
+        None => None,
+    };
+    let mut frames = match (&outputs.framebuffer, &args.framebuffer_out, args.framebuffer_every) {
+        (Some(framebuffer), Some(f), Some(every)) => Some((FrameDumper::new(framebuffer.clone(), f), every.max(1))),
+        _ => None,
+    };
+
+    print_info("Starting execution...");
+    let exit_code = loop {
+        let (step, pc) = (cpu.instruction_count().total(), cpu.pc().clone());
+        if let Err(halt) = cpu.execute_next(true) {
+            if let Halt::UnhandledException { .. } = halt { println!("{cpu}"); }
+            println!("The number of instructions done is: {:?}", cpu.instruction_count());
+            println!("{halt}");
+            if let Some(source) = halt.pc().and_then(|pc| source_map.get(pc.0)) { println!("  at {source}"); }
+            break halt.exit_code();
+        }
+        if let Some(trace) = &mut trace {
+            writeln!(trace, "{}", TraceRecord::last(&cpu, step, &pc, &source_map).to_json())?;
+        }
+        instructions_executed += 1;
+        if let Some((frames, every)) = &mut frames {
+            if instructions_executed % *every == 0 { frames.dump()?; }
+        }
+        if instructions_executed >= Processador::MAX_INSTRUCTION_RUN_SIZE {
+            println!("{} insturctions have been executed, so it seems like the program is non-halting. For the \
+                     sake of your terminal and your hard-drive, execution has been stoped", Processador::MAX_INSTRUCTION_RUN_SIZE);
+            break 0;
+        }
+    };
+    if let Some((mut frames, _)) = frames {
+        frames.dump()?;
+        print_info(&format!("{} frames have been written next to '{}'", frames.frames(), args.framebuffer_out.as_deref().unwrap_or_default()));
+    }
+    if let Some(mut trace) = trace {
+        trace.flush()?;
+        print_info(&format!("The trace has been written to '{}'", args.trace.as_deref().unwrap_or_default()));
+    }
+    std::process::exit(finish(&cpu, &args, outputs, exit_code)?);
+}
+
+/// Compare two traces written with `--trace`, and exit with 1 if they differ
+#[cfg(feature = "executable")]
+fn trace_diff(got: &str, expected: &str) -> ! {
+    let compare = || -> anyhow::Result<i32> {
+        let (got, expected) = (read_trace(&got)?, read_trace(&expected)?);
+        match compare_traces(&got, &expected) {
+            Ok(()) => {
+                println!("[CHECK]: the traces match ({} instructions)", got.len());
+                Ok(0)
+            },
+            Err(e) => {
+                println!("[CHECK]: {e}");
+                Ok(1)
+            },
+        }
+    };
+    match compare() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Run the program in the [Debugger], driven by the commands typed in (or by GDB, or by the keys
+/// of the [Tui])
+#[cfg(feature = "executable")]
+fn debug(args: CliArgs) -> ! {
+    set_quiet(true);
+    let run = || -> anyhow::Result<i32> {
+        let (mut debugger, outputs) = debugger(&args)?;
+        for location in &args.breakpoints {
+            // The same as the break command, so that it can have a condition too
+            debugger.command(&format!("break {location}"))?;
+        }
+        match args.gdb {
+            Some(port) => GdbStub::new(&mut debugger).serve(port)?,
+            None if args.tui => {
+                let source = std::fs::read_to_string(&args.input_file)?;
+                Tui::new(&mut debugger, &source).run()?;
+            },
+            None => debugger.repl()?,
+        }
+        set_quiet(false);
+        finish(debugger.cpu(), &args, outputs.take(), debugger.exit_code())
+    };
+    match run() {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(1);
+        },
+    }
+}
+
+/// Serve the [Debugger] to an editor through the Debug Adapter Protocol, over stdio. The arguments
+/// of `launch` are turned into command line arguments
+#[cfg(feature = "executable")]
+fn dap() -> ! {
+    set_quiet(true);
+    let mut server = DapServer::new(Box::new(|launch| {
+        let mut argv = vec!["sICmulador".to_string(), launch["program"].as_str().unwrap_or_default().to_string()];
+        for (field, flag) in [("io", "--io-file"), ("registers", "--reg-file"), ("memory", "--mfile")] {
+            if let Some(file) = launch[field].as_str() { argv.extend([flag.to_string(), file.to_string()]); }
+        }
+        if launch["simple"].as_bool().unwrap_or(false) { argv.push("--simple".into()); }
+        argv.extend(launch["args"].as_array().into_iter().flatten().filter_map(|a| a.as_str().map(String::from)));
+
+        let args = CliArgs::try_parse_from(argv)?;
+        // Stdin carries the protocol, so nothing else can read from it
+        if args.interactive_io || args.keyboard.as_deref() == Some("-") {
+            anyhow::bail!("--interactive-io and --keyboard - read from stdin, which the debug adapter is using");
+        }
+        let (debugger, _) = debugger(&args)?;
+        Ok(debugger)
+    }));
+    match server.run(std::io::stdin().lock(), std::io::stdout()) {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(1);
+        },
+    }
+}
+
+/// A [Debugger] for the program the command line gives, which sets it up again every time it
+/// starts over. Also gives the devices whose contents are shown once the program is done
+#[cfg(feature = "executable")]
+fn debugger(args: &CliArgs) -> anyhow::Result<(Debugger, SharedOutputs)> {
+    let outputs = Rc::new(RefCell::new(Outputs::default()));
+    let source = Rc::new(RefCell::new((Labels::default(), SourceMap::new())));
+    let mut debugger = Debugger::new(Box::new({
+        let (args, outputs, source) = (args.clone(), Rc::clone(&outputs), Rc::clone(&source));
+        move || {
+            let Session { cpu, outputs: o, labels, source_map } = setup(&args)?;
+            *outputs.borrow_mut() = o;
+            *source.borrow_mut() = (labels, source_map);
+            Ok(cpu)
+        }
+    }))?;
+    let (labels, source_map) = source.take();
+    debugger.set_labels(labels);
+    debugger.set_source_map(source_map);
+    Ok((debugger, outputs))
+}
+
+/// Devices whose contents are shown once the program is done
+#[cfg(feature = "executable")]
+#[derive(Default)]
+struct Outputs {
+    console: Option<Rc<RefCell<String>>>,
+    framebuffer: Option<Framebuffer>,
+}
+
+/// The [Outputs] of the last time the [Debugger] set up the program
+#[cfg(feature = "executable")]
+type SharedOutputs = Rc<RefCell<Outputs>>;
+
+/// The processor set up as the command line says, ready to start
+#[cfg(feature = "executable")]
+struct Session {
+    cpu: Processador,
+    outputs: Outputs,
+    labels: Labels,
+    /// Where in the source file each instruction comes from
+    source_map: SourceMap,
+}
+
+#[cfg(feature = "executable")]
+fn setup(args: &CliArgs) -> anyhow::Result<Session> {
+    let project = match &args.project {
+        Some(f) => read_project(f)?,
+        None => Project::default(),
+    };
+    let profile = args.profile.or(project.profile).unwrap_or_default();
+    print_info(&format!("Using the {profile} ISA profile"));
+
+    let io_timeline = match &args.io_file {
+        Some(f) => Some(read_io_timeline(f)?),
         None => {
             print_info("No IO file provided, starting without IO");
-            HashMap::new()
+            None
         },
     };
     let init_pc: ProgCounter = ProgCounter(args.prog_counter);
-    let registers = match args.reg_file {
-        Some(f) => read_registers(&f)?,
+    let registers = match &args.reg_file {
+        Some(f) => read_registers(f)?,
         None => {
             print_info("No initial registers file provided, starting with all 0");
             Registers::default()
         },
     };
 
-    let mut cpu = if args.simple {
+    let (mut cpu, labels, source_map) = if args.simple {
         let instructions = read_simple_instructions_file(&args.input_file)?;
-        let memory = if let Some(mem_file) = args.memory_file { read_memory(&mem_file)? }
+        let source_map = SourceMap::simple(&args.input_file, &std::fs::read_to_string(&args.input_file)?);
+        profile.validate(&instructions, &source_map)?;
+        let memory = if let Some(mem_file) = &args.memory_file { read_memory(mem_file)? }
             else { Memory::default() };
 
-        Processador::new( registers, memory, init_pc, instructions, io_system)
+        (Processador::new( registers, memory, init_pc, instructions, HashMap::new()), Labels::default(), source_map)
 
     } else {
-        if args.memory_file.is_some() { 
-            eprintln!("Initial memory file was provided, but no --simple flag: aborting");
-            std::process::exit(1);
+        if args.memory_file.is_some() {
+            anyhow::bail!("Initial memory file was provided, but no --simple flag: aborting");
         }
-        let Input { mem: memory, instructions } = parse_complete_file(&args.input_file, args.mem_init_addr.into(), args.prog_counter.into())?;
+        let Input { mem: memory, instructions, labels, source_map } = parse_complete_file(&args.input_file, args.mem_init_addr.into(), args.prog_counter.into())?;
+        profile.validate(&instructions, &source_map)?;
 
-        Processador::new( registers, memory, init_pc, instructions, io_system)
+        (Processador::new( registers, memory, init_pc, instructions, HashMap::new()), labels, source_map)
 
     };
 
-    print_info("\n\nStarting with state:");
-    println!("{cpu}");
+    if let Some(timeline) = io_timeline {
+        cpu.attach_device(i16::MIN..=i16::MAX, Box::new(timeline));
+    }
+    if let Some(keys) = &args.keyboard {
+        let source = if keys == "-" { KeySource::Terminal } else { KeySource::Queue(read_keys(keys)?) };
+        let ports = args.key_status_port.min(args.key_data_port)..=args.key_status_port.max(args.key_data_port);
+        let keyboard = Keyboard::new(MemAddr(args.key_status_port), MemAddr(args.key_data_port), source);
+        cpu.attach_device(ports, Box::new(keyboard));
+    }
+    if args.board || args.board_script.is_some() {
+        let ports = BoardPorts::default();
+        let inputs = match &args.board_script {
+            Some(f) => read_board_script(f, &ports)?,
+            None => IoTimeline::new(),
+        };
+        cpu.attach_device(ports.range(), Box::new(Board::new(ports, inputs)));
+    }
+    let console = if args.console || args.console_file.is_some() {
+        let console = Console::new(MemAddr(args.console_port));
+        let buffer = console.buffer();
+        cpu.attach_device(args.console_port..=args.console_port, Box::new(console));
+        Some(buffer)
+    } else { None };
+    let framebuffer = args.framebuffer.map(|mode| {
+        let framebuffer = Framebuffer::new(MemAddr(args.framebuffer_addr as i16), mode);
+        cpu.add_memory_hook(framebuffer.region(), Box::new(framebuffer.clone()));
+        framebuffer
+    });
+    if let Some(seed) = args.rng_seed.or(project.rng_seed) {
+        cpu.attach_device(args.rng_port..=args.rng_port, Box::new(Rng::new(MemAddr(args.rng_port), seed)));
+    }
+    for region in project.mmio.iter().chain(&args.mmio) {
+        cpu.map_io(region.clone());
+    }
+    if args.interactive_io {
+        cpu.set_io_fallback(Some(Box::new(StdinPrompt::new())));
+    }
+    if args.tlb {
+        cpu.set_mmu(Some(Mmu::new(args.itlb_entries, args.dtlb_entries)));
+    }
+    cpu.set_exception_handler(args.exception_handler.map(ProgCounter));
+    cpu.set_syscall_handler(args.syscall_handler.map(ProgCounter));
+    cpu.set_protect_io(args.protect_io);
+    cpu.set_profile(profile);
+    if args.user { cpu.set_mode(Mode::User); }
 
-    let mut instructions_executed = 0;
+    if args.no_loop_detection { cpu.set_loop_detection(false); }
 
-    print_info("Starting execution...");
-    loop {
-        cpu.execute_next(true);
-        instructions_executed += 1;
-        if instructions_executed >= Processador::MAX_INSTRUCTION_RUN_SIZE {
-            println!("{} insturctions have been executed, so it seems like the program is non-halting. For the \
-                     sake of your terminal and your hard-drive, execution has been stoped", Processador::MAX_INSTRUCTION_RUN_SIZE);
-            std::process::exit(0);
+    Ok(Session { cpu, outputs: Outputs { console, framebuffer }, labels, source_map })
+}
+
+/// Export and check the output of the program once it's done. Returns the final exit code
+#[cfg(feature = "executable")]
+fn finish(cpu: &Processador, args: &CliArgs, Outputs { console, framebuffer }: Outputs, exit_code: i32) -> anyhow::Result<i32> {
+    if let Some(console) = console {
+        let text = console.borrow();
+        println!("[CONSOLE]:\n{}", *text);
+        if let Some(f) = &args.console_file {
+            std::fs::write(f, &*text)?;
+            print_info(&format!("The console has been written to '{f}'"));
+        }
+    }
+    if let Some(framebuffer) = framebuffer {
+        println!("[FRAMEBUFFER]:\n{}", framebuffer.to_ascii());
+        if let Some(f) = &args.framebuffer_out {
+            std::fs::write(f, framebuffer.to_ppm())?;
+            print_info(&format!("The last frame has been written to '{f}'"));
+        }
+    }
+    if let Some(f) = &args.output_log {
+        let contents = if f.ends_with(".json") { output_to_json(cpu.output_log()) } else { output_to_csv(cpu.output_log()) };
+        std::fs::write(f, contents)?;
+        print_info(&format!("The output log has been written to '{f}'"));
+    }
+    if let Some(f) = &args.expected_output {
+        match compare_output(cpu.output_log(), &read_expected_output(f)?) {
+            Ok(()) => println!("[CHECK]: the output matches the expected one"),
+            Err(e) => {
+                println!("[CHECK]: {e}");
+                return Ok(1);
+            },
         }
     }
+    Ok(exit_code)
 }
 
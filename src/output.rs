@@ -0,0 +1,124 @@
+use std::{path::Path, fs::File, io::Read};
+
+use serde_json::json;
+
+use crate::{execute::{MemAddr, Value16Bit}, FileError, parse_value};
+
+/// A value written with `OUT`, as recorded in the [Processador](crate::Processador)'s output log
+#[derive(Debug, Clone)]
+pub struct OutputRecord {
+    /// Number of instructions executed before the `OUT`
+    pub step: usize,
+    /// Number of cycles elapsed before the `OUT`
+    pub cycle: usize,
+    /// Port that was written to
+    pub port: MemAddr,
+    /// Value that was written
+    pub value: Value16Bit,
+}
+
+/// Differences between the output of the program and the expected one
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum OutputMismatch {
+    #[error("output #{index} was '{got_value}' on port {got_port}, but '{expected_value}' on port {expected_port} was expected")]
+    Different { index: usize, got_port: MemAddr, got_value: Value16Bit, expected_port: MemAddr, expected_value: Value16Bit },
+    #[error("the program wrote {got} values, but {expected} were expected")]
+    Length { got: usize, expected: usize },
+}
+
+/// Export the output log as CSV, with a `step,cycle,port,value` header
+pub fn output_to_csv(log: &[OutputRecord]) -> String {
+    let mut out = String::from("step,cycle,port,value\n");
+    for r in log {
+        out.push_str(&format!("{},{},{},{}\n", r.step, r.cycle, r.port.0, r.value));
+    }
+    out
+}
+
+/// Export the output log as a JSON list of objects with the `step`, `cycle`, `port` and `value`
+/// keys
+pub fn output_to_json(log: &[OutputRecord]) -> String {
+    let records: Vec<_> = log.iter()
+        .map(|r| json!({ "step": r.step, "cycle": r.cycle, "port": r.port.0, "value": r.value.0 }))
+        .collect();
+    let mut out = serde_json::to_string_pretty(&records).unwrap_or_default();
+    out.push('\n');
+    out
+}
+
+/// Read the output a program is expected to write, as a list of `port,value` lines. Empty lines,
+/// lines starting with `#` and a header are ignored. The CSV exported by [output_to_csv] is
+/// accepted too (only its last two columns are used). Values may be in decimal, hex or `'c'`.
+///
+/// For example:
+/// ```txt
+/// port,value
+/// 5,0x0066
+/// 5,'a'
+/// ```
+pub fn read_expected_output(filename: &impl AsRef<Path>) -> anyhow::Result<Vec<(MemAddr, Value16Bit)>> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+
+    let mut expected = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.ends_with("port,value") { continue; }
+        expected.push(parse_output_line(line).ok_or(FileError::UnparsableOutput)?);
+    }
+    Ok(expected)
+}
+
+/// The port and value of a line of the expected output. The value is the last column, but it may be
+/// `','`, so every comma is tried (from the right) as the one before it
+fn parse_output_line(line: &str) -> Option<(MemAddr, Value16Bit)> {
+    line.rmatch_indices(',').find_map(|(i, _)| {
+        let value = parse_value(&line[i + 1..])?;
+        let port = parse_value(line[..i].rsplit(',').next()?)?;
+        Some((MemAddr(port), Value16Bit(value)))
+    })
+}
+
+/// Check that the output log has exactly the expected ports and values, in order
+pub fn compare_output(log: &[OutputRecord], expected: &[(MemAddr, Value16Bit)]) -> Result<(), OutputMismatch> {
+    for (index, (got, (port, value))) in log.iter().zip(expected).enumerate() {
+        if got.port != *port || got.value.0 != value.0 {
+            return Err(OutputMismatch::Different {
+                index,
+                got_port: got.port.clone(),
+                got_value: got.value.clone(),
+                expected_port: port.clone(),
+                expected_value: value.clone(),
+            });
+        }
+    }
+    if log.len() != expected.len() {
+        return Err(OutputMismatch::Length { got: log.len(), expected: expected.len() });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_output() {
+    let record = |step, port, value| OutputRecord { step, cycle: step * 2, port: MemAddr(port), value: Value16Bit(value) };
+    let log = [record(1, 5, 'a' as i16), record(3, 5, ',' as i16)];
+
+    assert_eq!(output_to_csv(&log), "step,cycle,port,value\n1,2,5,0x0061\n3,6,5,0x002C\n");
+    let json: serde_json::Value = serde_json::from_str(&output_to_json(&log)).unwrap();
+    assert_eq!(json[1], json!({ "step": 3, "cycle": 6, "port": 5, "value": 44 }));
+
+    let path = std::env::temp_dir().join(format!("sicmulador-expected-{}.csv", std::process::id()));
+    std::fs::write(&path, "# The two characters\nport,value\n5,'a'\n\n5,','\n").unwrap();
+    let expected = read_expected_output(&path).unwrap();
+    std::fs::write(&path, output_to_csv(&log)).unwrap();
+    assert_eq!(read_expected_output(&path).unwrap().len(), 2);
+    std::fs::write(&path, "5;'a'\n").unwrap();
+    assert!(read_expected_output(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(compare_output(&log, &expected).is_ok());
+    assert!(matches!(compare_output(&log[..1], &expected), Err(OutputMismatch::Length { got: 1, expected: 2 })));
+    let wrong = [record(1, 5, 'a' as i16), record(3, 6, ',' as i16)];
+    assert!(matches!(compare_output(&wrong, &expected), Err(OutputMismatch::Different { index: 1, .. })));
+}
@@ -68,6 +68,39 @@ macro_rules! generate_parse_match {
                     d: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
                     n: $parts.next().ok_or(ParseError::MissingImmediate)?.try_into()?,
             },
+            // TLB management
+            "WRPI" => Instruction::WRPI {
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    b: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "WRVI" => Instruction::WRVI {
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    b: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "WRPD" => Instruction::WRPD {
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    b: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "WRVD" => Instruction::WRVD {
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    b: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "FLUSH" => Instruction::FLUSH,
+            // System (S0-S7 are parsed just like R0-R7)
+            "RDS" => Instruction::RDS {
+                    d: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "WRS" => Instruction::WRS {
+                    d: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
+            "EI" => Instruction::EI,
+            "DI" => Instruction::DI,
+            "RETI" => Instruction::RETI,
+            "CALLS" => Instruction::CALLS {
+                    a: $parts.next().ok_or(ParseError::MissingReg)?.try_into()?,
+            },
             // My precious lil baby
             "NOP" =>  Instruction::NOP,
 
@@ -2,9 +2,8 @@ use std::{collections::HashMap, mem};
 use std::fs::File;
 use std::io::Read;
 
-use crate::{read_instructions, print_info};
-use crate::{ProgCounter, Memory, Instructions, FileError, execute::MemAddr};
-use nom::{IResult, bytes::complete::{tag, take_until}};
+use crate::{read_instructions, print_info, is_quiet};
+use crate::{ProgCounter, Memory, Instructions, FileError, execute::MemAddr, spec::Instruction, source_map::{SourceMap, SourceLocation}};
 use anyhow::Context;
 
 const DEFAULT_SPACE_FILLER_VALUE: i8 = 0;
@@ -37,7 +36,29 @@ pub struct Input {
     /// The memory, as specified by the .data section
     pub mem: Memory,
     /// The instructions, as specified by the .text section
-    pub instructions: Instructions
+    pub instructions: Instructions,
+    /// The labels of both sections
+    pub labels: Labels,
+    /// Where in the file each instruction comes from
+    pub source_map: SourceMap,
+}
+
+/// The labels of a program and the addresses they stand for
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    /// Labels of the .data section, which point to the memory
+    pub data: HashMap<String, MemAddr>,
+    /// Labels of the .text section, which point to instructions
+    pub text: HashMap<String, MemAddr>,
+}
+
+impl Labels {
+    /// The label (of either section) at the given address, if any. Labels of the .text section
+    /// are given for instructions, and those of the .data section for the memory
+    pub fn name_of(&self, addr: &MemAddr, instruction: bool) -> Option<&str> {
+        let labels = if instruction { &self.text } else { &self.data };
+        labels.iter().filter(|(_, a)| *a == addr).map(|(name, _)| name.as_str()).min()
+    }
 }
 
 /// The requirements for the file are quite particular. They are:
@@ -52,40 +73,49 @@ pub fn parse_complete_file(filename: &str, mem_addr: MemAddr, instr_addr: ProgCo
     let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
     let mut input = String::new();
     input_file.read_to_string(&mut input).context("could not read from file")?;
+    parse_source(filename, &input, mem_addr, instr_addr)
+}
+
+/// [parse_complete_file], once the file has been read
+fn parse_source(filename: &str, input: &str, mem_addr: MemAddr, instr_addr: ProgCounter) -> anyhow::Result<Input> {
+    // Every line that isn't empty (once the comments are gone) remembers where it comes from
+    let lines: Vec<SourceLocation> = input.lines().enumerate()
+        .map(|(i, line)| SourceLocation {
+            file: filename.into(),
+            line: i + 1,
+            column: line.len() - line.trim_start().len() + 1,
+            text: line.split(';').next().unwrap_or_default().trim().into(),
+            label: None,
+        })
+        .filter(|line| !line.text.is_empty())
+        .collect();
+    if !is_quiet() { println!("Input is: {}", lines.iter().map(|l| format!("{}\n", l.text)).collect::<String>()); }
 
-    input = input.lines()
-        .filter(|line| !line.is_empty())
-        .map(|line| {
-            let mut line = line.trim().split(';');
-            let mut l = line.next().unwrap().to_string(); // SAFETY: We're removed empty lines, each line must contain
-                                                          // _something_
-            l.push('\n');
-            l
-        }).collect();
-    println!("Input is: {input}");
-    
-    let data_tag: IResult<&str, &str> = tag(".data")(&input);
-    let (input, _) = data_tag.map_err(|e| e.to_owned()).context("input does not start with '.data': did you forget to use `--simple`?")?;
-    let directives: IResult<&str, &str> = take_until(".text")(input);
-    let (input, directives) = directives.map_err(|e| e.to_owned()).context("could not parse the directives")?;
-
-    let text_tag: IResult<&str, &str> = tag(".text")(input);
-    let (input, _) = text_tag.map_err(|e| e.to_owned()).context("input does not start with '.data'")?;
-    let text_area: IResult<&str, &str> = take_until(".end")(input);
-    let (_input, text_area) = text_area.map_err(|e| e.to_owned()).context("could not parse the data section, .end may be missing")?;
-
-
-    let (memory, env, ptrs) = parse_directives(directives, mem_addr)?;
-    println!("Preprocessed memory is:");
-    println!("-----------------------\n");
-    println!("{memory}");
-    println!("-----------------------\n");
-    let instructions = parse_instructions(text_area, &env, ptrs, &instr_addr)?;
+    let (_, lines) = lines.split_first().filter(|(first, _)| first.text.starts_with(".data"))
+        .context("input does not start with '.data': did you forget to use `--simple`?")?;
+    let text_start = lines.iter().position(|l| l.text.starts_with(".text")).context("could not parse the directives")?;
+    let (directives, text_area) = (&lines[..text_start], &lines[text_start + 1..]);
+    let text_end = text_area.iter().position(|l| l.text.starts_with(".end")).context("could not parse the data section, .end may be missing")?;
+    let text_area = &text_area[..text_end];
+
+    let directives: Vec<_> = directives.iter().map(|l| l.text.as_str()).collect();
+    let (memory, env, ptrs) = parse_directives(&directives.join("\n"), mem_addr)?;
+    if !is_quiet() {
+        println!("Preprocessed memory is:");
+        println!("-----------------------\n");
+        println!("{memory}");
+        println!("-----------------------\n");
+    }
+    let data_labels = ptrs.clone();
+    let (instructions, mut text_labels, source_map) = parse_instructions(text_area, &env, ptrs, &instr_addr)?;
+    text_labels.retain(|label, _| !data_labels.contains_key(label));
 
 
     Ok(Input {
         mem: memory,
         instructions,
+        labels: Labels { data: data_labels, text: text_labels },
+        source_map,
     })
 
 }
@@ -132,7 +162,7 @@ fn parse_directives(directives: &str, mut mem_addr: MemAddr) -> anyhow::Result<(
                 match command {
                     ".byte" => {
                         ptrs.insert(etiq.into(), mem_addr.clone());
-                        let bytes: Vec<_> = line.split(' ').map(|b| b.parse::<i8>()).collect();
+                        let bytes: Vec<_> = parts.map(|b| b.parse::<i8>()).collect();
                         for byte in bytes {
                             let byte = byte?;
                             memory.insert_byte(&mem_addr, byte);
@@ -164,7 +194,7 @@ fn parse_directives(directives: &str, mut mem_addr: MemAddr) -> anyhow::Result<(
 // 'Aliases' are String -> String maps, like `SIZE := 7`; 'Pointers' are labels
 // This is INCREDIBLE inefficient, there's a lot of reallocation and copying and whatever, but it
 // doesn't really matter
-fn parse_instructions(text: &str, env: &Aliases, mut ptrs: Pointers, pc: &ProgCounter) -> anyhow::Result<Instructions> {
+fn parse_instructions(text: &[SourceLocation], env: &Aliases, mut ptrs: Pointers, pc: &ProgCounter) -> anyhow::Result<(Instructions, Pointers, SourceMap)> {
     // THE PLAN:
     // Do it in passes, changing things like `lo(v)` for their value n things. When it's all
     // neat and tidy, run it by the function in `parsing.rs` :)
@@ -174,31 +204,26 @@ fn parse_instructions(text: &str, env: &Aliases, mut ptrs: Pointers, pc: &ProgCo
     //  - First check if it's a label. If it's not, check if it's an alias (labels take preference)
     // Third parse: full parsing
 
-    let original_text = text.to_string();
     let mut first_pc = pc.clone();
 
-    // First pass
-    for line in original_text.clone().lines().filter(|l| !l.is_empty()) {
-        let line = line.trim();
-        if let Some(colon_idx) = line.find(':') {
-            let etiq = &line[0..colon_idx];
+    // First pass. The lines left are the instructions, which remember the last label before them
+    let mut label = None;
+    let mut labelless_text: Vec<SourceLocation> = Vec::new();
+    for line in text {
+        if let Some(colon_idx) = line.text.find(':') {
+            let etiq = &line.text[0..colon_idx];
             ptrs.insert(etiq.to_string(), first_pc.clone().into());
-            print_info(&format!("PARSING: Line '{line}' has label '{etiq}' at addr {}", first_pc));
+            print_info(&format!("PARSING: Line '{}' has label '{etiq}' at addr {}", line.text, first_pc));
+            label = Some(etiq.trim().to_string());
         } else {
+            labelless_text.push(SourceLocation { label: label.clone(), ..line.clone() });
             first_pc.advance();
         }
     }
 
     mem::drop(first_pc);
-    let mut labelless_text: Vec<String> = original_text.clone()
-        .lines()
-        .filter(|l| !l.is_empty())
-        .filter(|l| l.find(':').is_none())
-        .map(|l| l.to_string())
-        .collect();
-
-    for (i, line) in labelless_text.iter_mut().enumerate() {
-        *line = line
+    let lowered_text: Vec<String> = labelless_text.iter().enumerate().map(|(i, line)| {
+        line.text
             .split(' ')
             .map(|word| { // Get lo() and hi()
                 if word.len() < 3 { word.into() }
@@ -219,15 +244,31 @@ fn parse_instructions(text: &str, env: &Aliases, mut ptrs: Pointers, pc: &ProgCo
                 } else { word }
             })
             .collect::<Vec<_>>()
-            .join(" ");
+            .join(" ")
+    }).collect();
+    let processed_text: String = lowered_text.join("\n");
+
+    if !is_quiet() {
+        println!("Preprocessed text is:");
+        println!("-----------------------\n");
+        println!("{processed_text}");
+        println!("-----------------------\n");
     }
-    let processed_text: String = labelless_text.join("\n");
+    let instructions = read_instructions(&processed_text).map_err(|e| {
+        // Point at the line it comes from, since the lowered one may not look like it at all
+        match lowered_text.iter().position(|l| Instruction::try_from(l.trim()).is_err()) {
+            Some(i) => e.context(format!("at {}", labelless_text[i])),
+            None => e,
+        }
+    })?;
 
-    println!("Preprocessed text is:");
-    println!("-----------------------\n");
-    println!("{processed_text}");
-    println!("-----------------------\n");
-    read_instructions(&processed_text)
+    let mut source_map = SourceMap::new();
+    let mut pc = pc.clone();
+    for location in labelless_text {
+        source_map.insert(pc.0, location);
+        pc.advance();
+    }
+    Ok((instructions, ptrs, source_map))
 }
 
 enum PartOfAddr {
@@ -245,3 +286,26 @@ fn get_part_of_label(word: &str, ptrs: &Pointers, part: PartOfAddr) -> String {
     format!("0x{:X}", value)
 }
 
+
+#[test]
+fn test_source_map() {
+    let input = ".data\n\tv: .word 1 2\n.text\n\tMOVI R0, lo(v) ; the address\n\nloop:\n\tLD R1, 0(R0)\n\tBZ R1, loop\n.end\n";
+    crate::set_quiet(true);
+    let map = parse_source("dir/prog.sisa", input, MemAddr(0x10), ProgCounter(0)).unwrap().source_map;
+    let bz = map.get(4).unwrap();
+    assert_eq!((bz.line, bz.column, bz.text.as_str(), bz.label.as_deref()), (8, 2, "BZ R1, loop", Some("loop")));
+    assert_eq!(bz.to_string(), "prog.sisa:8  BZ R1, loop");
+    assert_eq!(map.get(0).unwrap().text, "MOVI R0, lo(v)");
+    assert!(map.get(0).unwrap().label.is_none());
+    assert_eq!(map.at_line(5).map(|(pc, _)| pc), Some(2));
+    assert!(map.get(6).is_none());
+}
+
+#[test]
+fn test_byte_directive() {
+    // Only the values after `.byte` are bytes, not the label nor the directive itself
+    let (memory, _, pointers) = parse_directives("msg: .byte 72 105 0\nend: .byte -1", MemAddr(0x10)).unwrap();
+    assert_eq!(pointers["msg"], MemAddr(0x10));
+    assert_eq!(pointers["end"], MemAddr(0x13));
+    assert_eq!((0x10..0x14).map(|a| memory.get_byte(&MemAddr(a))).collect::<Vec<_>>(), vec![Some(72), Some(105), Some(0), Some(-1)]);
+}
@@ -0,0 +1,138 @@
+use std::{fmt, str::FromStr};
+
+use crate::{spec::Instruction, source_map::SourceMap, Instructions};
+
+const SISA_I: [&str; 20] = [
+    "AND", "OR", "XOR", "NOT", "ADD", "SUB", "SHA", "SHL", "CMPLT", "CMPLE", "CMPEQ", "CMPLTU",
+    "CMPLEU", "ADDI", "LD", "ST", "BZ", "BNZ", "MOVI", "MOVHI",
+];
+const SISA: [&str; 5] = ["LDB", "STB", "IN", "OUT", "JALR"];
+const SISA_SYS: [&str; 11] = [
+    "WRPI", "WRVI", "WRPD", "WRVD", "FLUSH", "RDS", "WRS", "EI", "DI", "RETI", "CALLS",
+];
+
+/// Which subset of the ISA programs are allowed to use. Each profile contains every instruction of
+/// the previous one:
+/// - `sisa-i`: the arithmetic, logic, comparison, LD/ST, branch and MOVI/MOVHI instructions
+/// - `sisa`: `sisa-i` plus LDB/STB, IN/OUT and JALR
+/// - `sisa-f`: `sisa` plus the floating point extension. No floating point instructions are
+///   simulated yet, so it accepts the same instructions as `sisa`
+/// - `sisa-sys`: `sisa-f` plus the system instructions (TLB, system registers, interrupts and
+///   `CALLS`). This is the default.
+///
+/// `NOP` is part of all of them. Each profile also says how many general purpose registers programs
+/// may use (see [register_count](IsaProfile::register_count)); for now all of them have eight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[allow(missing_docs)]
+pub enum IsaProfile {
+    SisaI,
+    Sisa,
+    SisaF,
+    #[default]
+    SisaSys,
+}
+
+/// Errors found when checking a program against an [IsaProfile]
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum ProfileError {
+    #[error("unknown ISA profile '{0}' (expected one of sisa-i, sisa, sisa-f or sisa-sys)")]
+    UnknownProfile(String),
+    #[error("instruction {verb} at address 0x{addr:0>4X} is not part of the {profile} profile{}", at(location))]
+    InstructionNotAllowed { verb: String, addr: i16, profile: IsaProfile, location: Option<String> },
+    #[error("register R{reg} used at address 0x{addr:0>4X} does not exist in the {profile} profile, which has {count} registers{}", at(location))]
+    RegisterNotAllowed { reg: u8, addr: i16, profile: IsaProfile, count: u8, location: Option<String> },
+}
+
+/// Where an error comes from in the source file, if it's known
+fn at(location: &Option<String>) -> String {
+    location.as_ref().map(|s| format!("\n  at {s}")).unwrap_or_default()
+}
+
+impl IsaProfile {
+    /// Whether the instruction is part of this profile
+    pub fn allows(&self, inst: &Instruction) -> bool {
+        let verb = &&*inst.get_verb();
+        verb == &"NOP"
+            || SISA_I.contains(verb)
+            || (*self != IsaProfile::SisaI && SISA.contains(verb))
+            || (*self == IsaProfile::SisaSys && SISA_SYS.contains(verb))
+    }
+
+    /// Number of general purpose registers that programs may use
+    pub fn register_count(&self) -> u8 { 8 }
+
+    /// The first register that the instruction uses but this profile doesn't have, if any
+    pub fn missing_register(&self, inst: &Instruction) -> Option<u8> {
+        inst.registers().into_iter().map(|r| r.0).find(|&r| r >= self.register_count())
+    }
+
+    /// Check that every instruction of the program belongs to this profile and that they only use
+    /// its registers. Errors point at the source line of the instruction, if `source_map` has it
+    pub fn validate(&self, instructions: &Instructions, source_map: &SourceMap) -> Result<(), ProfileError> {
+        let mut addrs: Vec<_> = instructions.keys().collect();
+        addrs.sort_by_key(|a| a.0);
+        for addr in addrs {
+            let inst = &instructions[addr];
+            let location = source_map.get(addr.0 as u16).map(|s| s.to_string());
+            if !self.allows(inst) {
+                return Err(ProfileError::InstructionNotAllowed { verb: inst.get_verb(), addr: addr.0, profile: *self, location });
+            }
+            if let Some(reg) = self.missing_register(inst) {
+                return Err(ProfileError::RegisterNotAllowed { reg, addr: addr.0, profile: *self, count: self.register_count(), location });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for IsaProfile {
+    type Err = ProfileError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sisa-i"   => Ok(IsaProfile::SisaI),
+            "sisa"     => Ok(IsaProfile::Sisa),
+            "sisa-f"   => Ok(IsaProfile::SisaF),
+            "sisa-sys" => Ok(IsaProfile::SisaSys),
+            other      => Err(ProfileError::UnknownProfile(other.into())),
+        }
+    }
+}
+
+impl fmt::Display for IsaProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            IsaProfile::SisaI   => "sisa-i",
+            IsaProfile::Sisa    => "sisa",
+            IsaProfile::SisaF   => "sisa-f",
+            IsaProfile::SisaSys => "sisa-sys",
+        })
+    }
+}
+
+#[test]
+fn test_profiles() {
+    use crate::read_instructions;
+
+    let instruction = |text: &str| read_instructions(text).unwrap().into_values().next().unwrap();
+    let (ldb, calls) = (instruction("LDB R2, 0(R1)"), instruction("CALLS R1"));
+    assert!(IsaProfile::SisaI.allows(&Instruction::NOP));
+    assert!(!IsaProfile::SisaI.allows(&ldb));
+    assert!(IsaProfile::Sisa.allows(&ldb) && IsaProfile::SisaF.allows(&ldb));
+    assert!(!IsaProfile::SisaF.allows(&calls));
+    assert!(IsaProfile::SisaSys.allows(&calls));
+
+    let text = "MOVI R1, 1\nIN R2, 3\nADD R3, R1, R2";
+    let (program, source_map) = (read_instructions(text).unwrap(), SourceMap::simple("prog.sisa", text));
+    assert!(IsaProfile::Sisa.validate(&program, &source_map).is_ok());
+    let error = IsaProfile::SisaI.validate(&program, &source_map).unwrap_err();
+    assert!(matches!(&error, ProfileError::InstructionNotAllowed { addr: 2, profile: IsaProfile::SisaI, .. }));
+    assert!(error.to_string().ends_with("at prog.sisa:2  IN R2, 3"));
+    assert!(IsaProfile::SisaI.missing_register(&instruction("ADD R7, R1, R2")).is_none());
+
+    assert_eq!("SISA-F".parse::<IsaProfile>().unwrap(), IsaProfile::SisaF);
+    for profile in [IsaProfile::SisaI, IsaProfile::Sisa, IsaProfile::SisaF, IsaProfile::SisaSys] {
+        assert_eq!(profile.to_string().parse::<IsaProfile>().unwrap(), profile);
+    }
+    assert!(matches!("sisa-x".parse::<IsaProfile>(), Err(ProfileError::UnknownProfile(p)) if p == "sisa-x"));
+}
@@ -0,0 +1,57 @@
+use std::{path::Path, fs::File, io::Read};
+
+use anyhow::Context;
+use boml::prelude::*;
+
+use crate::{FileError, IsaProfile, MmioRegion, norm_n};
+
+/// Settings that can be kept in a project file, in the TOML format, instead of being given through
+/// the command line every time. Options given through the command line take preference.
+///
+/// For example:
+/// ```txt
+/// profile = "sisa-i"
+/// rng_seed = 42
+///
+/// [[mmio]]  # LD/ST on 0xFF00-0xFF0F go to the IO ports 0 to 7
+/// start = "0xFF00"
+/// end = "0xFF0F"
+/// port = 0
+/// ```
+#[derive(Debug, Default)]
+pub struct Project {
+    /// Subset of the ISA that the program may use
+    pub profile: Option<IsaProfile>,
+    /// Memory regions handed over to the IO bus
+    pub mmio: Vec<MmioRegion>,
+    /// Seed of the pseudo-random number generator
+    pub rng_seed: Option<u64>,
+}
+
+/// Read a [Project] file
+pub fn read_project(filename: &impl AsRef<Path>) -> anyhow::Result<Project> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+    let table: Toml = boml::parse(&contents).or(Err(FileError::UnparsableProject))?;
+
+    let mut project = Project::default();
+    if let Ok(profile) = table.get_string("profile") {
+        project.profile = Some(profile.parse()?);
+    }
+    if let Ok(seed) = table.get_integer("rng_seed") {
+        project.rng_seed = Some(seed as u64);
+    }
+    if let Ok(regions) = table.get_array("mmio") {
+        let addr = |region: &TomlTable, key| region.get_string(key).ok()
+            .and_then(|v| norm_n(v).ok()).ok_or(FileError::UnparsableProject);
+        for region in regions {
+            let region = region.as_table().ok_or(FileError::UnparsableProject)?;
+            let first_port = region.get_integer("port").or(Err(FileError::UnparsableProject))? as i16;
+            let (start, end) = (addr(region, "start")?, addr(region, "end")?);
+            project.mmio.push(MmioRegion::new(start..=end, first_port).ok_or(FileError::UnparsableProject)
+                .with_context(|| format!("the mmio region 0x{start:X}-0x{end:X} ends before it starts"))?);
+        }
+    }
+    Ok(project)
+}
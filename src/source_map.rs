@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+/// Where an instruction comes from in the source file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    /// The file, as it was given
+    pub file: String,
+    /// Line of the file, starting at 1
+    pub line: usize,
+    /// Column where the instruction starts, starting at 1
+    pub column: usize,
+    /// The instruction as it was written, without the comment (so still with its labels, `lo()`,
+    /// `hi()` and aliases)
+    pub text: String,
+    /// The last label of the .text section before the instruction (or on it), if any
+    pub label: Option<String>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = std::path::Path::new(&self.file).file_name().map(|f| f.to_string_lossy()).unwrap_or_default();
+        write!(f, "{file}:{}  {}", self.line, self.text)
+    }
+}
+
+/// Where in the source file each instruction, by its address, comes from. It's kept through the
+/// whole preprocessor, so that the instructions can be shown as they were written (like
+/// `first_example.sisa:14  BZ R4, fi_if`) instead of as they were lowered (`BZ R4, 0x05`)
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    locations: BTreeMap<u16, SourceLocation>,
+}
+
+impl SourceMap {
+    #[allow(missing_docs)]
+    pub fn new() -> Self { Self::default() }
+
+    /// The map of a file given with `--simple`, where every line is an instruction
+    pub fn simple(file: &str, contents: &str) -> Self {
+        let mut map = Self::new();
+        for (i, line) in contents.lines().enumerate() {
+            let column = line.len() - line.trim_start().len() + 1;
+            map.insert((i * 2) as u16, SourceLocation { file: file.into(), line: i + 1, column, text: line.trim().into(), label: None });
+        }
+        map
+    }
+
+    pub(crate) fn insert(&mut self, pc: u16, location: SourceLocation) {
+        self.locations.insert(pc, location);
+    }
+
+    /// Where the instruction at `pc` comes from
+    pub fn get(&self, pc: u16) -> Option<&SourceLocation> { self.locations.get(&pc) }
+
+    /// The first instruction on the given line or after it, and where it is
+    pub fn at_line(&self, line: usize) -> Option<(u16, &SourceLocation)> {
+        self.locations.iter().filter(|(_, l)| l.line >= line).min_by_key(|(_, l)| l.line).map(|(pc, l)| (*pc, l))
+    }
+
+    /// Every instruction, by its address, and where it comes from
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &SourceLocation)> {
+        self.locations.iter().map(|(pc, l)| (*pc, l))
+    }
+
+    #[allow(missing_docs)]
+    pub fn is_empty(&self) -> bool { self.locations.is_empty() }
+}
@@ -122,6 +122,37 @@ pub enum Instruction {
         a: RegLabel,
         d: RegLabel,
     },
+    WRPI {
+        a: RegLabel,
+        b: RegLabel,
+    },
+    WRVI {
+        a: RegLabel,
+        b: RegLabel,
+    },
+    WRPD {
+        a: RegLabel,
+        b: RegLabel,
+    },
+    WRVD {
+        a: RegLabel,
+        b: RegLabel,
+    },
+    FLUSH,
+    RDS {
+        d: RegLabel,
+        a: RegLabel,
+    },
+    WRS {
+        d: RegLabel,
+        a: RegLabel,
+    },
+    EI,
+    DI,
+    RETI,
+    CALLS {
+        a: RegLabel,
+    },
     NOP,
 }
 
@@ -156,7 +187,106 @@ impl Instruction {
             Instruction::IN     { .. } => "IN",
             Instruction::OUT    { .. } => "OUT",
             Instruction::JALR   { .. } => "JALR",
+            Instruction::WRPI   { .. } => "WRPI",
+            Instruction::WRVI   { .. } => "WRVI",
+            Instruction::WRPD   { .. } => "WRPD",
+            Instruction::WRVD   { .. } => "WRVD",
+            Instruction::FLUSH         => "FLUSH",
+            Instruction::RDS    { .. } => "RDS",
+            Instruction::WRS    { .. } => "WRS",
+            Instruction::EI            => "EI",
+            Instruction::DI            => "DI",
+            Instruction::RETI          => "RETI",
+            Instruction::CALLS  { .. } => "CALLS",
             Instruction::NOP           => "NOP",
         }.into()
     }
+
+    /// Every register (general purpose or system) that the instruction names
+    pub fn registers(&self) -> Vec<&RegLabel> {
+        match self {
+            Instruction::AND { a, b, d } | Instruction::OR { a, b, d } | Instruction::XOR { a, b, d }
+            | Instruction::ADD { a, b, d } | Instruction::SUB { a, b, d } | Instruction::SHA { a, b, d }
+            | Instruction::SHL { a, b, d } | Instruction::CMPLT { a, b, d } | Instruction::CMPLE { a, b, d }
+            | Instruction::CMPEQ { a, b, d } | Instruction::CMPLTU { a, b, d }
+            | Instruction::CMPLEU { a, b, d }                => vec![a, b, d],
+            Instruction::NOT { a, d } | Instruction::ADDI { a, d, .. }
+            | Instruction::LD { a, d, .. } | Instruction::LDB { a, d, .. }
+            | Instruction::JALR { a, d } | Instruction::RDS { d, a }
+            | Instruction::WRS { d, a }                      => vec![a, d],
+            Instruction::ST { a, b, .. } | Instruction::STB { a, b, .. }
+            | Instruction::WRPI { a, b } | Instruction::WRVI { a, b }
+            | Instruction::WRPD { a, b } | Instruction::WRVD { a, b } => vec![a, b],
+            Instruction::BZ { a, .. } | Instruction::BNZ { a, .. }
+            | Instruction::CALLS { a }                       => vec![a],
+            Instruction::MOVI { d, .. } | Instruction::MOVHI { d, .. }
+            | Instruction::IN { d, .. }                      => vec![d],
+            Instruction::OUT { n, .. }                       => vec![n],
+            Instruction::FLUSH | Instruction::EI | Instruction::DI
+            | Instruction::RETI | Instruction::NOP            => vec![],
+        }
+    }
+
+    /// The general purpose register that the instruction writes, if any
+    pub fn destination(&self) -> Option<&RegLabel> {
+        match self {
+            Instruction::AND { d, .. } | Instruction::OR { d, .. } | Instruction::XOR { d, .. }
+            | Instruction::NOT { d, .. } | Instruction::ADD { d, .. } | Instruction::SUB { d, .. }
+            | Instruction::SHA { d, .. } | Instruction::SHL { d, .. } | Instruction::CMPLT { d, .. }
+            | Instruction::CMPLE { d, .. } | Instruction::CMPEQ { d, .. } | Instruction::CMPLTU { d, .. }
+            | Instruction::CMPLEU { d, .. } | Instruction::ADDI { d, .. } | Instruction::LD { d, .. }
+            | Instruction::LDB { d, .. } | Instruction::MOVI { d, .. } | Instruction::MOVHI { d, .. }
+            | Instruction::IN { d, .. } | Instruction::JALR { d, .. } | Instruction::RDS { d, .. } => Some(d),
+            _ => None,
+        }
+    }
+
+    /// The general purpose registers that the instruction reads
+    pub fn sources(&self) -> Vec<&RegLabel> {
+        match self {
+            Instruction::MOVHI { d, .. }                     => vec![d],
+            Instruction::RDS { .. }                          => vec![],
+            Instruction::WRS { a, .. }                       => vec![a],
+            // The destination always comes last
+            _ => {
+                let mut registers = self.registers();
+                if self.destination().is_some() { registers.pop(); }
+                registers
+            },
+        }
+    }
+}
+
+// Written back the way it's parsed, e.g. `LD R1, 0x02(R3)`
+impl std::fmt::Display for Instruction {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = self.get_verb();
+        match self {
+            Instruction::AND { a, b, d } | Instruction::OR { a, b, d } | Instruction::XOR { a, b, d }
+            | Instruction::ADD { a, b, d } | Instruction::SUB { a, b, d } | Instruction::SHA { a, b, d }
+            | Instruction::SHL { a, b, d } | Instruction::CMPLT { a, b, d } | Instruction::CMPLE { a, b, d }
+            | Instruction::CMPEQ { a, b, d } | Instruction::CMPLTU { a, b, d }
+            | Instruction::CMPLEU { a, b, d }             => write!(f, "{verb} R{}, R{}, R{}", d.0, a.0, b.0),
+            Instruction::NOT { a, d } | Instruction::JALR { a, d } => write!(f, "{verb} R{}, R{}", d.0, a.0),
+            Instruction::ADDI { a, b, d }                 => write!(f, "{verb} R{}, R{}, {b}", d.0, a.0),
+            Instruction::LD { d, a, offset }
+            | Instruction::LDB { d, a, offset }           => write!(f, "{verb} R{}, {offset}(R{})", d.0, a.0),
+            Instruction::ST { offset, a, b }
+            | Instruction::STB { offset, a, b }           => write!(f, "{verb} {offset}(R{}), R{}", a.0, b.0),
+            Instruction::BZ { a, offset }
+            | Instruction::BNZ { a, offset }              => write!(f, "{verb} R{}, {offset}", a.0),
+            Instruction::MOVI { d, n }
+            | Instruction::MOVHI { d, n }                 => write!(f, "{verb} R{}, {n}", d.0),
+            Instruction::IN { d, n }                      => write!(f, "{verb} R{}, {n}", d.0),
+            Instruction::OUT { d, n }                     => write!(f, "{verb} {d}, R{}", n.0),
+            Instruction::WRPI { a, b } | Instruction::WRVI { a, b }
+            | Instruction::WRPD { a, b } | Instruction::WRVD { a, b } => write!(f, "{verb} R{}, R{}", a.0, b.0),
+            Instruction::RDS { d, a }                     => write!(f, "{verb} R{}, S{}", d.0, a.0),
+            Instruction::WRS { d, a }                     => write!(f, "{verb} S{}, R{}", d.0, a.0),
+            Instruction::CALLS { a }                      => write!(f, "{verb} R{}", a.0),
+            Instruction::FLUSH | Instruction::EI | Instruction::DI
+            | Instruction::RETI | Instruction::NOP         => write!(f, "{verb}"),
+        }
+    }
 }
@@ -0,0 +1,175 @@
+use std::fmt;
+
+use crate::exception::{Access, Exception};
+use crate::execute::MemAddr;
+
+/// Pages are 4KB long, so the top four bits of an address are the page number and the remaining
+/// twelve are the offset inside of it
+const PAGE_OFFSET_BITS: u16 = 12;
+
+/// Default number of entries of each TLB: one per page, so that all of them start out mapped
+pub const DEFAULT_TLB_ENTRIES: usize = 16;
+
+/// A single entry of a [Tlb]. It translates the virtual page `virtual_tag` to the physical page
+/// `physical_tag`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlbEntry {
+    /// Virtual page number (4 bits)
+    pub virtual_tag: u8,
+    /// Physical page number (4 bits)
+    pub physical_tag: u8,
+    /// Whether the page can only be read. Writing to it raises [Exception::ProtectedPage]
+    pub read_only: bool,
+    /// Whether the page is present in memory. Accessing it otherwise raises
+    /// [Exception::InvalidPage]
+    pub present: bool,
+    /// Whether the entry holds a translation at all. Cleared by `FLUSH`
+    pub valid: bool,
+}
+
+/// A fully associative Translation Lookaside Buffer. The processor has two of them, one for
+/// instructions and one for data (see [Mmu]).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tlb(Vec<TlbEntry>);
+
+impl Tlb {
+    /// Create a new TLB with `entries` entries. Entry `i` starts mapping virtual page `i` to
+    /// physical page `i`, so with [DEFAULT_TLB_ENTRIES] (or more) entries programs that don't touch
+    /// the TLB keep working as if there were none. With fewer, pages from `entries` on miss until
+    /// they are mapped
+    pub fn new(entries: usize) -> Self {
+        Self((0..entries).map(|i| TlbEntry {
+            virtual_tag: (i & 0xF) as u8,
+            physical_tag: (i & 0xF) as u8,
+            read_only: false,
+            present: true,
+            valid: true,
+        }).collect())
+    }
+
+    /// All the entries, in order
+    pub fn entries(&self) -> &[TlbEntry] { &self.0 }
+
+    /// Behaviour of `WRP*`: bits 3..0 of `value` are the physical page, bit 4 is the read-only bit
+    /// and bit 5 is the present bit. Only the lower bits of `index` are used, so it always wraps
+    /// around to a valid entry
+    pub fn write_physical(&mut self, index: i16, value: i16) {
+        let entry = self.entry_mut(index);
+        entry.physical_tag = (value & 0xF) as u8;
+        entry.read_only = value & (1 << 4) != 0;
+        entry.present = value & (1 << 5) != 0;
+    }
+
+    /// Behaviour of `WRV*`: bits 3..0 of `value` are the virtual page. Writing the virtual tag makes
+    /// the entry valid again
+    pub fn write_virtual(&mut self, index: i16, value: i16) {
+        let entry = self.entry_mut(index);
+        entry.virtual_tag = (value & 0xF) as u8;
+        entry.valid = true;
+    }
+
+    /// Invalidate every entry
+    pub fn flush(&mut self) {
+        self.0.iter_mut().for_each(|e| e.valid = false);
+    }
+
+    /// Translate a virtual address into a physical one
+    pub fn translate(&self, addr: &MemAddr, access: Access) -> Result<MemAddr, Exception> {
+        let virtual_page = ((addr.0 as u16) >> PAGE_OFFSET_BITS) as u8;
+        let offset = addr.0 as u16 & ((1 << PAGE_OFFSET_BITS) - 1);
+
+        let entry = self.0.iter()
+            .find(|e| e.valid && e.virtual_tag == virtual_page)
+            .ok_or(Exception::TlbMiss { access, addr: addr.clone() })?;
+        if !entry.present {
+            return Err(Exception::InvalidPage { access, addr: addr.clone() });
+        }
+        if entry.read_only && access == Access::Write {
+            return Err(Exception::ProtectedPage { access, addr: addr.clone() });
+        }
+
+        Ok(MemAddr((((entry.physical_tag as u16) << PAGE_OFFSET_BITS) | offset) as i16))
+    }
+
+    fn entry_mut(&mut self, index: i16) -> &mut TlbEntry {
+        let len = self.0.len();
+        &mut self.0[index as u16 as usize % len]
+    }
+}
+
+/// The Memory Management Unit: the instruction TLB, used when fetching, and the data TLB, used by
+/// LD/LDB/ST/STB
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mmu {
+    /// Translates the addresses of the instructions
+    pub instructions: Tlb,
+    /// Translates the addresses of the data
+    pub data: Tlb,
+}
+
+impl Mmu {
+    /// Create a new MMU with the given number of entries in each TLB
+    pub fn new(instruction_entries: usize, data_entries: usize) -> Self {
+        Self {
+            instructions: Tlb::new(instruction_entries.max(1)),
+            data: Tlb::new(data_entries.max(1)),
+        }
+    }
+
+    /// Translate an address, choosing which TLB to use depending on the kind of access
+    pub fn translate(&self, addr: &MemAddr, access: Access) -> Result<MemAddr, Exception> {
+        match access {
+            Access::Fetch => self.instructions.translate(addr, access),
+            Access::Read | Access::Write => self.data.translate(addr, access),
+        }
+    }
+}
+
+impl Default for Mmu {
+    fn default() -> Self { Self::new(DEFAULT_TLB_ENTRIES, DEFAULT_TLB_ENTRIES) }
+}
+
+impl fmt::Display for Tlb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut out = String::new();
+        for (i, e) in self.0.iter().enumerate() {
+            if !e.valid { continue; }
+            out.push_str(&format!("{i}: 0x{:X} -> 0x{:X}{}{} | ", e.virtual_tag, e.physical_tag,
+                if e.read_only { " (r)" } else { "" },
+                if e.present { "" } else { " (!p)" }));
+        }
+        write!(f, "{out}")
+    }
+}
+
+#[test]
+fn test_tlb_translation() {
+    let mut tlb = Tlb::new(4);
+    assert_eq!(tlb.translate(&MemAddr(0x1234), Access::Read).unwrap(), MemAddr(0x1234));
+
+    tlb.write_virtual(0, 0xA);
+    tlb.write_physical(0, 0b01_0011); // Read-only, not present, page 3
+    assert!(matches!(tlb.translate(&MemAddr(0x0010), Access::Read), Err(Exception::TlbMiss { .. })));
+    assert!(matches!(tlb.translate(&MemAddr(0xA010u16 as i16), Access::Read), Err(Exception::InvalidPage { .. })));
+
+    tlb.write_physical(0, 0b11_0011); // Read-only, present, page 3
+    assert_eq!(tlb.translate(&MemAddr(0xA010u16 as i16), Access::Fetch).unwrap(), MemAddr(0x3010));
+    assert!(matches!(tlb.translate(&MemAddr(0xA010u16 as i16), Access::Write), Err(Exception::ProtectedPage { .. })));
+
+    tlb.flush();
+    assert!(matches!(tlb.translate(&MemAddr(0x1234), Access::Read), Err(Exception::TlbMiss { .. })));
+}
+
+#[test]
+fn test_default_tlb_maps_every_page() {
+    use crate::{read_instructions, Registers, Memory, ProgCounter, Processador};
+
+    let mut memory = Memory::new();
+    memory.insert_word(&MemAddr(0x8000u16 as i16), 0x1234);
+    let program = read_instructions("MOVI R1, 0x00\nMOVHI R1, 0x80\nLD R2, 0(R1)").unwrap();
+    let mut cpu = Processador::new(Registers::default(), memory, ProgCounter(0), program, Default::default());
+    cpu.set_mmu(Some(Mmu::default()));
+    crate::set_quiet(true);
+    for _ in 0..3 { cpu.execute_next(false).unwrap(); }
+    assert_eq!(cpu.register(2), 0x1234);
+}
@@ -0,0 +1,175 @@
+use std::{path::Path, fs::File, io::Read};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::{execute::{Processador, ProgCounter}, source_map::SourceMap, FileError};
+
+/// What one instruction did, as written to a trace with `--trace`: one JSON object per line, like
+/// ```txt
+/// {"instruction":"LD R1, 0x00(R0)","io_reads":[],"io_writes":[],"memory":[],"pc":4,"registers":[{"register":1,"value":12}],"source":"first_example.sisa:7  LD R1, 0(R0)","step":2}
+/// ```
+/// Memory writes are given byte by byte, with the byte's value (from 0 to 255)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceRecord {
+    /// Number of instructions executed before this one
+    pub step: usize,
+    /// Address of the instruction
+    pub pc: u16,
+    /// The instruction, as it was executed
+    pub instruction: String,
+    /// Where it comes from in the source file, if it's known
+    pub source: Option<String>,
+    /// General purpose registers written, with their new values
+    pub registers: Vec<(u8, i16)>,
+    /// Bytes of memory written, with their new values
+    pub memory: Vec<(i16, u8)>,
+    /// Ports read with `IN`, and the values read
+    pub io_reads: Vec<(i16, i16)>,
+    /// Ports written with `OUT`, and the values written
+    pub io_writes: Vec<(i16, i16)>,
+}
+
+impl TraceRecord {
+    /// The record of the instruction the processor just executed, which was at `pc` after `step`
+    /// instructions
+    pub fn last(cpu: &Processador, step: usize, pc: &ProgCounter, source_map: &SourceMap) -> Self {
+        let effects = cpu.last_effects();
+        Self {
+            step,
+            pc: pc.0,
+            instruction: cpu.instruction_at(pc).map(|i| i.to_string()).unwrap_or_default(),
+            source: source_map.get(pc.0).map(|s| s.to_string()),
+            registers: effects.register_writes.iter().map(|(r, _, new)| (*r, *new)).collect(),
+            memory: effects.memory_writes.iter().map(|(a, _, new)| (a.0, *new as u8)).collect(),
+            io_reads: effects.io_reads.iter().map(|(p, v)| (p.0, *v)).collect(),
+            io_writes: effects.io_writes.iter().map(|(p, v)| (p.0, *v)).collect(),
+        }
+    }
+
+    /// The record as a single line of JSON
+    pub fn to_json(&self) -> String {
+        let pairs = |list: &[(i16, i16)]| list.iter().map(|(p, v)| json!({ "port": p, "value": v })).collect::<Vec<_>>();
+        let mut record = json!({
+            "step": self.step,
+            "pc": self.pc,
+            "instruction": self.instruction,
+            "registers": self.registers.iter().map(|(r, v)| json!({ "register": r, "value": v })).collect::<Vec<_>>(),
+            "memory": self.memory.iter().map(|(a, v)| json!({ "address": a, "value": v })).collect::<Vec<_>>(),
+            "io_reads": pairs(&self.io_reads),
+            "io_writes": pairs(&self.io_writes),
+        });
+        if let Some(source) = &self.source { record["source"] = json!(source); }
+        record.to_string()
+    }
+
+    /// Read a record written by [to_json](TraceRecord::to_json)
+    pub fn from_json(line: &str) -> Option<Self> {
+        let record: Value = serde_json::from_str(line).ok()?;
+        let pairs = |key: &str, first: &str| -> Option<Vec<(i64, i64)>> {
+            record[key].as_array()?.iter().map(|p| Some((p[first].as_i64()?, p["value"].as_i64()?))).collect()
+        };
+        let ports = |key: &str| -> Option<Vec<(i16, i16)>> {
+            Some(pairs(key, "port")?.into_iter().map(|(p, v)| (p as i16, v as i16)).collect())
+        };
+        Some(Self {
+            step: record["step"].as_u64()? as usize,
+            pc: record["pc"].as_u64()? as u16,
+            instruction: record["instruction"].as_str()?.into(),
+            source: record["source"].as_str().map(String::from),
+            registers: pairs("registers", "register")?.into_iter().map(|(r, v)| (r as u8, v as i16)).collect(),
+            memory: pairs("memory", "address")?.into_iter().map(|(a, v)| (a as i16, v as u8)).collect(),
+            io_reads: ports("io_reads")?,
+            io_writes: ports("io_writes")?,
+        })
+    }
+}
+
+impl std::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "step {}, {}: ", self.step, ProgCounter(self.pc))?;
+        match &self.source {
+            Some(source) => write!(f, "{source}")?,
+            None => write!(f, "{}", self.instruction)?,
+        }
+        let effects: Vec<_> = self.registers.iter().map(|(r, v)| format!("R{r} = 0x{v:0>4X}"))
+            .chain(self.memory.iter().map(|(a, v)| format!("[0x{a:0>4X}] = 0x{v:0>2X}")))
+            .chain(self.io_reads.iter().map(|(p, v)| format!("read 0x{v:0>4X} from port {p}")))
+            .chain(self.io_writes.iter().map(|(p, v)| format!("wrote 0x{v:0>4X} to port {p}")))
+            .collect();
+        if !effects.is_empty() { write!(f, " -> {}", effects.join("; "))?; }
+        Ok(())
+    }
+}
+
+/// Where two traces stop doing the same
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum TraceDivergence {
+    #[error("record #{index} differs in its {what}:\n  expected: {expected}\n  got:      {got}")]
+    Different { index: usize, what: &'static str, expected: Box<TraceRecord>, got: Box<TraceRecord> },
+    #[error("the trace has {got} records, but {expected} were expected{}", next.as_ref().map(|r| format!(", the first one that isn't in both being:\n  {r}")).unwrap_or_default())]
+    Length { got: usize, expected: usize, next: Option<Box<TraceRecord>> },
+}
+
+/// Read a trace written with `--trace`
+pub fn read_trace(filename: &impl AsRef<Path>) -> anyhow::Result<Vec<TraceRecord>> {
+    let mut input_file = File::open(filename).or(Err(FileError::FileNotFound))?;
+    let mut contents = String::new();
+    input_file.read_to_string(&mut contents).or(Err(FileError::ReadingError))?;
+
+    contents.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| TraceRecord::from_json(line).ok_or(FileError::UnparsableTrace).with_context(|| format!("could not read line {} of the trace", i + 1)))
+        .collect()
+}
+
+/// Find the first record where two traces differ: in the step, the PC, the registers or memory
+/// written, or the IO. The text of the instructions isn't compared, so the traces of different
+/// programs (or of different versions of the simulator) can be compared too
+pub fn compare_traces(got: &[TraceRecord], expected: &[TraceRecord]) -> Result<(), TraceDivergence> {
+    for (index, (g, e)) in got.iter().zip(expected).enumerate() {
+        let what = if g.step != e.step { "step" }
+            else if g.pc != e.pc { "PC" }
+            else if g.registers != e.registers { "registers written" }
+            else if g.memory != e.memory { "memory written" }
+            else if g.io_reads != e.io_reads { "IO reads" }
+            else if g.io_writes != e.io_writes { "IO writes" }
+            else { continue };
+        return Err(TraceDivergence::Different { index, what, expected: Box::new(e.clone()), got: Box::new(g.clone()) });
+    }
+    if got.len() != expected.len() {
+        let next = got.get(expected.len()).or(expected.get(got.len())).cloned().map(Box::new);
+        return Err(TraceDivergence::Length { got: got.len(), expected: expected.len(), next });
+    }
+    Ok(())
+}
+
+#[test]
+fn test_traces() {
+    use crate::{read_instructions, Registers, Memory, MemAddr, Value16Bit};
+    use std::collections::HashMap;
+
+    let trace = |program: &str| {
+        let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), read_instructions(program).unwrap(),
+            HashMap::from([(MemAddr(1), Value16Bit(7))]));
+        let mut records = Vec::new();
+        loop {
+            let (step, pc) = (cpu.instruction_count().total(), cpu.pc().clone());
+            if cpu.execute_next(false).is_err() { break records; }
+            records.push(TraceRecord::last(&cpu, step, &pc, &SourceMap::new()));
+        }
+    };
+    crate::set_quiet(true);
+    let reference = trace("IN R1, 1\nMOVI R2, 0x10\nST 0x00(R2), R1\nOUT 2, R1");
+    assert_eq!(reference[0].io_reads, vec![(1, 7)]);
+    assert_eq!(reference[2].memory, vec![(0x10, 7), (0x11, 0)]);
+    let line = reference[2].to_json();
+    assert_eq!(TraceRecord::from_json(&line), Some(reference[2].clone()));
+    assert!(compare_traces(&reference, &reference).is_ok());
+
+    let other = trace("IN R1, 1\nMOVI R2, 0x12\nST 0x00(R2), R1\nOUT 2, R1");
+    assert!(matches!(compare_traces(&other, &reference), Err(TraceDivergence::Different { index: 1, what: "registers written", .. })));
+    let shorter = trace("IN R1, 1\nMOVI R2, 0x10");
+    assert!(matches!(compare_traces(&shorter, &reference), Err(TraceDivergence::Length { got: 2, expected: 4, .. })));
+}
@@ -0,0 +1,326 @@
+use std::{io::{self, Write}, panic::{self, PanicHookInfo}, sync::Arc};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEventKind},
+    queue, execute,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+use crate::{debugger::{condition_failure, Debugger, Stop}, execute::{MemAddr, ProgCounter}, expression::ExprError};
+
+const KEYS: &str = "s step  n next  c continue  u step back  b breakpoint  ↑↓ line  PgUp/PgDn memory  r reset  q quit";
+/// Bytes in each row of the memory view
+const ROW_BYTES: i16 = 8;
+
+/// How a piece of text is drawn
+#[derive(Clone, Copy, PartialEq)]
+enum Style {
+    Normal,
+    /// The line that's about to run
+    Current,
+    /// Something that the last command changed
+    Changed,
+    /// A line with a breakpoint
+    Breakpoint,
+    Title,
+}
+
+/// A line of a pane, made of pieces drawn differently
+type Line = Vec<(String, Style)>;
+
+/// Keeps the terminal raw and on the alternate screen while it's alive, and puts it back the way it
+/// was when dropped. If something panics meanwhile, the terminal is put back before the panic is
+/// reported, so that the message can be read
+struct TerminalGuard {
+    previous_hook: Arc<dyn Fn(&PanicHookInfo) + Sync + Send>,
+}
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        let previous_hook: Arc<dyn Fn(&PanicHookInfo) + Sync + Send> = panic::take_hook().into();
+        panic::set_hook(Box::new({
+            let previous_hook = Arc::clone(&previous_hook);
+            move |info| {
+                Self::restore();
+                previous_hook(info);
+            }
+        }));
+        Ok(Self { previous_hook })
+    }
+
+    fn restore() {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // While panicking the hook has already restored it (and the hook can't be changed)
+        if std::thread::panicking() { return; }
+        let previous_hook = Arc::clone(&self.previous_hook);
+        panic::set_hook(Box::new(move |info| previous_hook(info)));
+        Self::restore();
+    }
+}
+
+/// A full-screen view of a [Debugger]: the source with the line about to run highlighted, the
+/// registers (with the ones that just changed highlighted), the memory around the `.data` labels,
+/// the IO ports and the values written with `OUT`. It's driven by single keys (see [KEYS]).
+pub struct Tui<'a> {
+    debugger: &'a mut Debugger,
+    /// The lines of the source file
+    source: Vec<String>,
+    /// Line selected to toggle breakpoints on
+    cursor: usize,
+    registers_before: [i16; 8],
+    memory_start: i16,
+    message: String,
+}
+
+impl<'a> Tui<'a> {
+    /// View the given debugger, whose program comes from `source`. Without its source map (see
+    /// [Debugger::set_source_map]), the instructions are listed instead
+    pub fn new(debugger: &'a mut Debugger, source: &str) -> Self {
+        let memory_start = debugger.labels().data.values().map(|a| a.0).min().unwrap_or(0x4000) & !(ROW_BYTES - 1);
+        let registers_before = Self::registers(debugger);
+        let mut tui = Self {
+            debugger,
+            source: source.lines().map(String::from).collect(),
+            cursor: 0,
+            registers_before,
+            memory_start,
+            message: String::new(),
+        };
+        tui.cursor = tui.current_line().unwrap_or(0);
+        tui
+    }
+
+    /// Take over the terminal until `q` is pressed
+    pub fn run(&mut self) -> io::Result<()> {
+        let _terminal = TerminalGuard::new()?;
+        self.event_loop(&mut io::stdout())
+    }
+
+    fn event_loop(&mut self, out: &mut impl Write) -> io::Result<()> {
+        loop {
+            let (width, height) = terminal::size()?;
+            self.draw(out, width, height)?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press { continue; }
+            let before = Self::registers(self.debugger);
+            let stop = match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                KeyCode::Char('s') | KeyCode::Right => Some(self.debugger.step(1)),
+                KeyCode::Char('n') => Some(self.debugger.step_over()),
+                KeyCode::Char('c') => Some(self.debugger.cont()),
+                KeyCode::Char('u') | KeyCode::Left => Some(self.debugger.step_back(1)),
+                KeyCode::Char('r') => match self.debugger.reset() {
+                    Ok(stop) => Some(stop.unwrap_or(Stop::Done)),
+                    Err(e) => { self.message = format!("could not start over: {e}"); None },
+                },
+                KeyCode::Char('b') => { self.toggle_breakpoint(); None },
+                KeyCode::Up => { self.cursor = self.cursor.saturating_sub(1); None },
+                KeyCode::Down => { self.cursor = (self.cursor + 1).min(self.source.len().saturating_sub(1)); None },
+                KeyCode::PageUp => { self.memory_start = self.memory_start.wrapping_sub(ROW_BYTES * 8); None },
+                KeyCode::PageDown => { self.memory_start = self.memory_start.wrapping_add(ROW_BYTES * 8); None },
+                _ => None,
+            };
+            if let Some(stop) = stop {
+                self.registers_before = before;
+                self.message = describe(&stop);
+                self.cursor = self.current_line().unwrap_or(self.cursor);
+            }
+        }
+    }
+
+    /// Add a breakpoint on the first instruction at or after the selected line, or remove it
+    fn toggle_breakpoint(&mut self) {
+        let Some((pc, _)) = self.debugger.source_map().at_line(self.cursor + 1) else {
+            self.message = "there is no instruction there".into();
+            return;
+        };
+        match self.debugger.breakpoints().iter().find(|b| b.pc == pc).map(|b| b.id) {
+            Some(id) => {
+                let _ = self.debugger.remove_breakpoint(id);
+                self.message = format!("Removed breakpoint {id}");
+            },
+            None => match self.debugger.add_breakpoint(&format!("0x{pc:X}")) {
+                Ok(id) => self.message = format!("Breakpoint {id} at {}", ProgCounter(pc)),
+                Err(e) => self.message = e.to_string(),
+            },
+        }
+    }
+
+    fn registers(debugger: &Debugger) -> [i16; 8] {
+        std::array::from_fn(|i| debugger.cpu().register(i as u8))
+    }
+
+    /// Index (starting at 0) of the source line about to run
+    fn current_line(&self) -> Option<usize> {
+        self.debugger.source_map().get(self.debugger.cpu().pc().0).map(|l| l.line - 1)
+    }
+
+    /// Draw every pane on a `width` x `height` screen
+    fn draw(&self, out: &mut impl Write, width: u16, height: u16) -> io::Result<()> {
+        queue!(out, Clear(ClearType::All))?;
+        let left = width * 3 / 5;
+        let right = width - left;
+        let body = height.saturating_sub(1);
+        let memory_rows = 10.min(body.saturating_sub(5));
+
+        self.pane(out, (0, 0), (left, body), "Source", self.source_lines(body.saturating_sub(1) as usize))?;
+        self.pane(out, (left, 0), (right, 5), "Registers", self.register_lines())?;
+        self.pane(out, (left, 5), (right, memory_rows + 1), "Memory", self.memory_lines(memory_rows as i16))?;
+        self.pane(out, (left, 6 + memory_rows), (right, body.saturating_sub(6 + memory_rows)), "IO", self.io_lines())?;
+
+        let status = if self.message.is_empty() { KEYS.to_string() } else { format!("{}  |  {KEYS}", self.message) };
+        queue!(out, MoveTo(0, body), SetAttribute(Attribute::Reverse), Print(fit(&status, width as usize)), SetAttribute(Attribute::Reset))?;
+        out.flush()
+    }
+
+    /// Draw a pane at `(x, y)` of size `(width, height)`, with its title on the first row
+    fn pane(&self, out: &mut impl Write, (x, y): (u16, u16), (width, height): (u16, u16), title: &str, lines: Vec<Line>) -> io::Result<()> {
+        if height == 0 { return Ok(()); }
+        let title = vec![(format!("── {title} {}", "─".repeat(width as usize)), Style::Title)];
+        for (row, line) in std::iter::once(title).chain(lines).take(height as usize).enumerate() {
+            queue!(out, MoveTo(x, y + row as u16))?;
+            let mut left = width.saturating_sub(1) as usize;
+            for (text, style) in line {
+                let text: String = text.chars().take(left).collect();
+                left -= text.chars().count();
+                match style {
+                    Style::Normal => {},
+                    Style::Current => queue!(out, SetAttribute(Attribute::Reverse))?,
+                    Style::Changed => queue!(out, SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold))?,
+                    Style::Breakpoint => queue!(out, SetForegroundColor(Color::Red))?,
+                    Style::Title => queue!(out, SetForegroundColor(Color::Cyan))?,
+                }
+                queue!(out, Print(text), SetAttribute(Attribute::Reset), ResetColor)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn source_lines(&self, rows: usize) -> Vec<Line> {
+        let source_map = self.debugger.source_map();
+        let breakpoints: Vec<_> = self.debugger.breakpoints().iter().filter_map(|b| source_map.get(b.pc)).map(|l| l.line).collect();
+        if self.source.is_empty() || source_map.is_empty() { return self.instruction_lines(rows); }
+
+        let current = self.current_line();
+        let first = self.cursor.saturating_sub(rows / 2).min(self.source.len().saturating_sub(rows));
+        self.source.iter().enumerate().skip(first).take(rows).map(|(i, text)| {
+            let marker = if breakpoints.contains(&(i + 1)) { ("●", Style::Breakpoint) } else { (" ", Style::Normal) };
+            let cursor = if i == self.cursor { ">" } else { " " };
+            let style = if Some(i) == current { Style::Current } else { Style::Normal };
+            vec![(marker.0.into(), marker.1), (format!("{cursor}{:>4} ", i + 1), Style::Normal), (text.replace('\t', "    "), style)]
+        }).collect()
+    }
+
+    /// The instructions around the PC, for programs without line information
+    fn instruction_lines(&self, rows: usize) -> Vec<Line> {
+        let pc = self.debugger.cpu().pc().0;
+        let first = pc.saturating_sub((rows / 2) as u16 * 2);
+        (0..rows as u16).map(|i| first.wrapping_add(i * 2)).map(|addr| {
+            let pc_addr = ProgCounter(addr);
+            let inst = self.debugger.cpu().instruction_at(&pc_addr).map(|i| i.to_string()).unwrap_or_default();
+            let breakpoint = self.debugger.breakpoints().iter().any(|b| b.pc == addr);
+            let style = if addr == pc { Style::Current } else { Style::Normal };
+            vec![(if breakpoint { "●" } else { " " }.into(), Style::Breakpoint), (format!("{pc_addr}: "), Style::Normal), (inst, style)]
+        }).collect()
+    }
+
+    fn register_lines(&self) -> Vec<Line> {
+        let cpu = self.debugger.cpu();
+        let mut lines: Vec<Line> = (0..2).map(|row| (0..4).map(|col| {
+            let i = row * 4 + col;
+            let value = cpu.register(i as u8);
+            let style = if value != self.registers_before[i] { Style::Changed } else { Style::Normal };
+            (format!("R{i} {value:0>4X}  "), style)
+        }).collect()).collect();
+        lines.push(vec![(format!("PC {}  S7 {:0>4X}  steps {}", cpu.pc(), cpu.system_register(7), cpu.instruction_count().total()), Style::Normal)]);
+        lines
+    }
+
+    fn memory_lines(&self, rows: i16) -> Vec<Line> {
+        let cpu = self.debugger.cpu();
+        let labels = &self.debugger.labels().data;
+        (0..rows).map(|row| {
+            let start = self.memory_start.wrapping_add(row * ROW_BYTES);
+            let bytes: String = (0..ROW_BYTES).map(|i| match cpu.memory().get_byte(&MemAddr(start.wrapping_add(i))) {
+                Some(b) => format!(" {b:0>2X}"),
+                None => " --".into(),
+            }).collect();
+            let mut here: Vec<_> = labels.iter()
+                .filter(|(_, a)| a.0.wrapping_sub(start) >= 0 && a.0.wrapping_sub(start) < ROW_BYTES)
+                .map(|(name, _)| name.as_str())
+                .collect();
+            here.sort_unstable();
+            let changed = cpu.last_effects().memory_writes.iter().any(|(a, ..)| a.0.wrapping_sub(start) >= 0 && a.0.wrapping_sub(start) < ROW_BYTES);
+            vec![
+                (format!("{start:0>4X}"), Style::Normal),
+                (bytes, if changed { Style::Changed } else { Style::Normal }),
+                (format!(" {}", here.join(" ")), Style::Title),
+            ]
+        }).collect()
+    }
+
+    fn io_lines(&self) -> Vec<Line> {
+        let cpu = self.debugger.cpu();
+        let ports = cpu.io_ports().into_iter().map(|(port, value)| vec![(format!("port {port}: {value}"), Style::Normal)]);
+        let outputs = cpu.output_log().iter().rev().map(|r| vec![(format!("step {}: {} on port {}", r.step, r.value, r.port), Style::Normal)]);
+        ports.chain([vec![("Written with OUT (newest first):".to_string(), Style::Title)]]).chain(outputs).collect()
+    }
+}
+
+/// What a stop means, in a single line
+fn describe(stop: &Stop) -> String {
+    match stop {
+        Stop::Done => String::new(),
+        Stop::Halted(halt) => halt.to_string(),
+        Stop::Breakpoint { id, error, .. } => format!("Breakpoint {id}{}", condition_note(error)),
+        Stop::Watchpoint { id, what, error, .. } => format!("Watchpoint {id}: {what}{}", condition_note(error)),
+        Stop::Start => "reached the first instruction remembered".into(),
+        Stop::Limit => "stopped after too many instructions".into(),
+    }
+}
+
+/// Why the condition of a stop couldn't be evaluated, to go after it
+fn condition_note(error: &Option<ExprError>) -> String {
+    condition_failure(error).map(|e| format!(" ({e})")).unwrap_or_default()
+}
+
+/// Cut or pad `text` to exactly `width` characters
+fn fit(text: &str, width: usize) -> String {
+    let mut text: String = text.chars().take(width).collect();
+    let len = text.chars().count();
+    text.extend(std::iter::repeat_n(' ', width - len));
+    text
+}
+
+#[test]
+fn test_tui_draws_panes() {
+    use crate::{read_instructions, Processador, Registers, Memory, SourceMap};
+    use std::collections::HashMap;
+
+    crate::set_quiet(true);
+    let mut debugger = Debugger::new(Box::new(|| {
+        let instructions = read_instructions("MOVI R1, 0x05\nADDI R2, R1, 1")?;
+        Ok(Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new()))
+    })).unwrap();
+    let source = "MOVI R1, 5\nADDI R2, R1, 1";
+    debugger.set_source_map(SourceMap::simple("test.sisa", source));
+    debugger.step(1);
+    let tui = Tui::new(&mut debugger, source);
+    assert_eq!(tui.current_line(), Some(1));
+    let mut screen = Vec::new();
+    tui.draw(&mut screen, 100, 30).unwrap();
+    let screen = String::from_utf8_lossy(&screen);
+    assert!(screen.contains("ADDI R2, R1, 1"));
+    assert!(screen.contains("R1 0005"));
+    assert_eq!(fit("abc", 5), "abc  ");
+}
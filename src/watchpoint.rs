@@ -0,0 +1,183 @@
+use std::ops::RangeInclusive;
+
+use crate::{execute::{Processador, MemAddr}, debugger::DebugError, expression::Expression, preprocessor::Labels, parse_value};
+
+/// What a [Watchpoint] watches
+#[derive(Debug, Clone)]
+pub enum WatchTarget {
+    /// The general purpose register `Ri`
+    Register(u8),
+    /// The bytes of memory in this (inclusive) range
+    Memory(RangeInclusive<u16>),
+}
+
+/// Which accesses to its target trigger a [Watchpoint]
+#[derive(Debug, Clone)]
+pub enum WatchKind {
+    /// Any read
+    Read,
+    /// Any write, even if the value stays the same
+    Write,
+    /// A write that changes the value
+    Change,
+}
+
+impl std::fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WatchKind::Read => write!(f, "read"),
+            WatchKind::Write => write!(f, "write"),
+            WatchKind::Change => write!(f, "change"),
+        }
+    }
+}
+
+/// Stops the program right after an instruction reads, writes or changes a register or some memory
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    /// Number that identifies it (shared with the breakpoints)
+    pub id: usize,
+    /// The target, as it was given (e.g. `max` or `R3`)
+    pub name: String,
+    #[allow(missing_docs)]
+    pub target: WatchTarget,
+    #[allow(missing_docs)]
+    pub kind: WatchKind,
+    /// Only stop if this holds right after the instruction, where `new` is the value the target
+    /// was accessed with
+    pub condition: Option<Expression>,
+    /// Times it has been triggered (even if its condition didn't hold)
+    pub hits: usize,
+}
+
+impl Watchpoint {
+    /// Parse the arguments of the `watch` command: a target (`R3`, an address, a label of the .data
+    /// section or a `START-END` range), then optionally `read`, `write` or `change` (the default)
+    /// and a condition on the new value, like `== 5`, which is short for `if new == 5`. Anything
+    /// after `if` is an [Expression] that has to hold too, like `if R1 > 0`
+    pub fn parse(id: usize, args: &[&str], labels: &Labels) -> Result<Self, DebugError> {
+        let (args, condition) = match args.iter().position(|a| *a == "if") {
+            Some(i) => (&args[..i], Some(args[i + 1..].join(" "))),
+            None => (args, None),
+        };
+        let (name, mut rest) = args.split_first().ok_or(DebugError::MissingArgument("register, address or label"))?;
+        let target = match name.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (address(start, labels)?, address(end, labels)?);
+                if start > end { return Err(DebugError::InvalidRange(name.to_string())); }
+                WatchTarget::Memory(start..=end)
+            },
+            None if name.starts_with(['R', 'r']) && name.len() == 2 => WatchTarget::Register(crate::debugger::register(name)?),
+            None => {
+                let addr = address(name, labels)? & !1;
+                WatchTarget::Memory(addr..=addr + 1)
+            },
+        };
+        let mut kind = WatchKind::Change;
+        if let Some((access, others)) = rest.split_first() {
+            match *access {
+                "read" => { kind = WatchKind::Read; rest = others; },
+                "write" => { kind = WatchKind::Write; rest = others; },
+                "change" => rest = others,
+                _ => {},
+            }
+        }
+        let condition = match (rest, condition) {
+            ([], condition) => condition,
+            (rest, _) if !matches!(kind, WatchKind::Change) => return Err(DebugError::InvalidCondition(rest.join(" "))),
+            (rest, None) => Some(format!("new {}", rest.join(" "))),
+            (rest, Some(condition)) => Some(format!("new {} && ({condition})", rest.join(" "))),
+        };
+        let condition = condition.map(|c| Expression::parse(&c, labels)).transpose()?;
+        Ok(Self { id, name: name.to_string(), target, kind, condition, hits: 0 })
+    }
+
+    /// If the last instruction executed did something to the target that triggers this
+    /// watchpoint, describe it, along with the value the target was accessed with (the whole word,
+    /// or the byte that was accessed of a range)
+    pub fn check(&self, cpu: &Processador) -> Option<(String, i16)> {
+        let effects = cpu.last_effects();
+        match (&self.target, &self.kind) {
+            (WatchTarget::Register(r), WatchKind::Read) => effects.register_reads.contains(r)
+                .then(|| (format!("R{r} read (0x{:0>4X})", cpu.register(*r)), cpu.register(*r))),
+            (WatchTarget::Register(r), kind) => {
+                let &(_, old, new) = effects.register_writes.iter().find(|(w, ..)| w == r)?;
+                triggers(kind, old, new).then(|| (format!("R{r} written: 0x{old:0>4X} -> 0x{new:0>4X}"), new))
+            },
+            (WatchTarget::Memory(range), WatchKind::Read) => {
+                let addr = effects.memory_reads.iter().find(|a| range.contains(&(a.0 as u16)))?;
+                let value = match range.end().checked_sub(*range.start()) {
+                    Some(1) => cpu.memory().get_word(&MemAddr(*range.start() as i16)),
+                    _ => cpu.memory().get_byte(addr).map(i16::from),
+                };
+                Some((format!("{} read at 0x{:0>4X}", self.name, addr.0), value.unwrap_or_default()))
+            },
+            (WatchTarget::Memory(range), kind) => {
+                let writes: Vec<_> = effects.memory_writes.iter().filter(|(a, ..)| range.contains(&(a.0 as u16))).collect();
+                if writes.is_empty() { return None; }
+                if range.end().checked_sub(*range.start()) == Some(1) {
+                    // A single word
+                    let new = cpu.memory().get_word(&MemAddr(*range.start() as i16)).unwrap_or_default();
+                    let byte = |addr: u16| writes.iter().find(|(a, ..)| a.0 as u16 == addr).map(|(_, old, _)| *old)
+                        .unwrap_or(cpu.memory().get_byte(&MemAddr(addr as i16))).unwrap_or_default();
+                    let old = ((byte(*range.end()) as i16) << 8) | (byte(*range.start()) as u8 as i16);
+                    return triggers(kind, old, new).then(|| (format!("{} written: 0x{old:0>4X} -> 0x{new:0>4X}", self.name), new));
+                }
+                writes.iter().find(|(_, old, new)| triggers(kind, old.unwrap_or_default() as i16, *new as i16))
+                    .map(|(a, old, new)| (format!("{} written at 0x{:0>4X}: 0x{:0>2X} -> 0x{new:0>2X}", self.name, a.0, old.unwrap_or_default()), *new as i16))
+            },
+        }
+    }
+}
+
+/// Whether writing `new` over `old` triggers a watchpoint of the given kind
+fn triggers(kind: &WatchKind, old: i16, new: i16) -> bool {
+    match kind {
+        WatchKind::Read => false,
+        WatchKind::Write => true,
+        WatchKind::Change => old != new,
+    }
+}
+
+/// An address, given as a number or as a label of the .data section
+fn address(input: &str, labels: &Labels) -> Result<u16, DebugError> {
+    match labels.data.get(input) {
+        Some(addr) => Ok(addr.0 as u16),
+        None => parse_value(input).map(|a| a as u16).ok_or_else(|| DebugError::UnknownLocation(input.into())),
+    }
+}
+
+#[test]
+fn test_watchpoints() {
+    use crate::{read_instructions, expression::Context, Registers, Memory, ProgCounter};
+    use std::collections::HashMap;
+
+    let mut labels = Labels::default();
+    labels.data.insert("max".into(), MemAddr(0x10));
+    let instructions = read_instructions("MOVI R1, 0x10\nMOVI R2, 0x07\nST 0x00(R1), R2\nLD R3, 0x00(R1)\nST 0x00(R1), R2").unwrap();
+    let mut cpu = Processador::new(Registers::default(), Memory::new(), ProgCounter(0), instructions, HashMap::new());
+    crate::set_quiet(true);
+    let stops = |w: &Watchpoint, cpu: &Processador| w.check(cpu).is_some_and(|(_, value)| {
+        w.condition.as_ref().is_none_or(|c| c.holds(&Context { cpu, hits: 0, value: Some(value) }).unwrap())
+    });
+
+    let changed = Watchpoint::parse(1, &["max"], &labels).unwrap();
+    let seven = Watchpoint::parse(2, &["max", "==", "7"], &labels).unwrap();
+    let read = Watchpoint::parse(3, &["max", "read"], &labels).unwrap();
+    let written = Watchpoint::parse(4, &["0x10-0x11", "write"], &labels).unwrap();
+    let r2 = Watchpoint::parse(5, &["R2", "change", ">", "5"], &labels).unwrap();
+    assert!(Watchpoint::parse(6, &["nowhere"], &labels).is_err());
+    assert!(matches!(Watchpoint::parse(6, &["0x11-0x10"], &labels), Err(DebugError::InvalidRange(_))));
+    assert!(matches!(Watchpoint::parse(6, &["0xFFFE"], &labels).unwrap().target, WatchTarget::Memory(r) if r == (0xFFFE..=0xFFFF)));
+    assert!(Watchpoint::parse(6, &["0x7FFF-0x8001"], &labels).is_ok());
+    assert!(Watchpoint::parse(6, &["R2", "read", "==", "1"], &labels).is_err());
+    assert!(Watchpoint::parse(6, &["R2", "if", "R2", ">"], &labels).is_err());
+    assert!(Watchpoint::parse(6, &["R2", "if", "R2", ">", "5"], &labels).unwrap().condition.is_some());
+    assert_eq!(Watchpoint::parse(6, &["R2", "<", "3", "if", "R1"], &labels).unwrap().condition.unwrap().to_string(), "new < 3 && (R1)");
+
+    let mut triggered = Vec::new();
+    while cpu.execute_next(false).is_ok() {
+        triggered.push([&changed, &seven, &read, &written, &r2].iter().filter(|w| stops(w, &cpu)).map(|w| w.id).collect::<Vec<_>>());
+    }
+    assert_eq!(triggered, vec![vec![], vec![5], vec![1, 2, 4], vec![3], vec![4]]);
+}